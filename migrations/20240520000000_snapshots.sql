@@ -0,0 +1,6 @@
+ALTER TABLE accounts ADD COLUMN imported_nonce BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE spent_legacy_outputs ALTER COLUMN transaction_id DROP NOT NULL;
+ALTER TABLE spent_legacy_outputs ADD COLUMN transaction_hash BYTEA CHECK (octet_length(transaction_hash) = 32);
+UPDATE spent_legacy_outputs SET transaction_hash = transactions.hash
+FROM transactions WHERE transactions.id = spent_legacy_outputs.transaction_id;
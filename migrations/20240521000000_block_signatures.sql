@@ -0,0 +1 @@
+ALTER TABLE blocks ADD COLUMN signature BYTEA CHECK (octet_length(signature) = 65);
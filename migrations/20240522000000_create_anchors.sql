@@ -0,0 +1,12 @@
+CREATE TABLE anchors(
+  id BIGSERIAL PRIMARY KEY,
+  block_number BIGINT NOT NULL UNIQUE REFERENCES blocks(number) ON DELETE RESTRICT,
+  block_hash BYTEA NOT NULL CHECK (octet_length(block_hash) = 32),
+  txid BYTEA NOT NULL UNIQUE CHECK (octet_length(txid) = 32),
+  psbt BYTEA NOT NULL,
+  funding_outpoints TEXT[] NOT NULL,
+  bitcoin_block_hash BYTEA CHECK (octet_length(bitcoin_block_hash) = 32),
+  bitcoin_block_height BIGINT,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  confirmed_at TIMESTAMP
+);
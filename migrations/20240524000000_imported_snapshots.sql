@@ -0,0 +1,6 @@
+ALTER TABLE accounts ADD COLUMN imported_balance BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE imported_snapshots (
+  height BIGINT PRIMARY KEY,
+  imported_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+CREATE INDEX ledger_transaction_id_index ON ledger(transaction_id);
+CREATE INDEX ledger_debtor_id_index ON ledger(debtor_id);
+CREATE INDEX ledger_creditor_id_index ON ledger(creditor_id);
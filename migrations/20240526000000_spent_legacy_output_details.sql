@@ -0,0 +1,6 @@
+ALTER TABLE spent_legacy_outputs ADD COLUMN script_type TEXT;
+ALTER TABLE spent_legacy_outputs ADD COLUMN amount BIGINT;
+
+-- Outputs spent before these columns existed, or imported from a snapshot, are
+-- filled in from the UTXO snapshot the first time upgrade stats are requested
+CREATE INDEX spent_legacy_outputs_without_details ON spent_legacy_outputs (id) WHERE script_type IS NULL;
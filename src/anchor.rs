@@ -0,0 +1,392 @@
+use crate::{
+    bitcoin_legacy::{encode_varint, sha256d::Sha256d},
+    constants::{
+        ANCHOR_FEE_RATE, ANCHOR_INTERVAL, ANCHOR_RESERVATION_TIMEOUT, LAST_LEGACY_BLOCK_NUMBER,
+    },
+    db::{
+        confirm_anchor, get_anchor_reservations, get_anchors, get_head_block,
+        get_last_anchored_block_number, insert_anchor, replace_anchor_txid, Anchor,
+    },
+    error::{Error, Result},
+    rpc::client,
+};
+use digest::Digest;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use tokio::time::{self, Duration};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const ANCHOR_MAGIC: &[u8; 4] = b"BTC2";
+const OP_RETURN: u8 = 0x6a;
+const DUST_LIMIT: u64 = 546;
+// Opts in to replace-by-fee so stuck anchors can be bumped
+const SEQUENCE: u32 = 0xfffffffd;
+const PSBT_MAGIC: &[u8; 5] = b"psbt\xff";
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+
+// Rough vsizes of a segwit input, the anchor output, a change output and the
+// transaction overhead
+const INPUT_VSIZE: u64 = 68;
+const COMMITMENT_OUTPUT_VSIZE: u64 = 55;
+const CHANGE_OUTPUT_VSIZE: u64 = 43;
+const OVERHEAD_VSIZE: u64 = 11;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Utxo {
+    // Display (big endian) order as reported by bitcoind
+    #[serde(with = "hex::serde")]
+    pub txid: [u8; 32],
+    pub vout: u32,
+    #[serde(rename = "scriptPubKey", with = "hex::serde")]
+    pub script_pubkey: Vec<u8>,
+    #[serde(rename = "amount", deserialize_with = "btc_to_sats")]
+    pub value: u64,
+}
+
+fn btc_to_sats<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let btc: f64 = Deserialize::deserialize(deserializer)?;
+    Ok((btc * 100_000_000.0).round() as u64)
+}
+
+impl Utxo {
+    fn outpoint(&self) -> String {
+        format!("{}:{}", hex::encode(self.txid), self.vout)
+    }
+}
+
+pub struct UnsignedTransaction {
+    pub inputs: Vec<Utxo>,
+    pub outputs: Vec<(u64, Vec<u8>)>,
+}
+
+impl UnsignedTransaction {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = 2i32.to_le_bytes().to_vec();
+        bytes.extend(encode_varint(self.inputs.len()));
+        for input in &self.inputs {
+            bytes.extend(input.txid.iter().rev());
+            bytes.extend(input.vout.to_le_bytes());
+            bytes.extend(encode_varint(0));
+            bytes.extend(SEQUENCE.to_le_bytes());
+        }
+        bytes.extend(encode_varint(self.outputs.len()));
+        for (value, script_pubkey) in &self.outputs {
+            bytes.extend(value.to_le_bytes());
+            bytes.extend(encode_varint(script_pubkey.len()));
+            bytes.extend(script_pubkey);
+        }
+        bytes.extend(0u32.to_le_bytes());
+        bytes
+    }
+
+    // Only stable once signed if every input is segwit, which `witness_utxo` assumes anyway
+    pub fn txid(&self) -> [u8; 32] {
+        let mut txid: [u8; 32] = Sha256d::digest(self.serialize()).into();
+        txid.reverse();
+        txid
+    }
+
+    // BIP 174 PSBT with a witness UTXO for each input so external signers can sign it
+    pub fn psbt(&self) -> Vec<u8> {
+        let unsigned_transaction = self.serialize();
+        let mut bytes = PSBT_MAGIC.to_vec();
+        bytes.extend(encode_varint(1));
+        bytes.push(PSBT_GLOBAL_UNSIGNED_TX);
+        bytes.extend(encode_varint(unsigned_transaction.len()));
+        bytes.extend(unsigned_transaction);
+        bytes.push(0x00);
+        for input in &self.inputs {
+            let witness_utxo = [
+                &input.value.to_le_bytes()[..],
+                &encode_varint(input.script_pubkey.len()),
+                &input.script_pubkey,
+            ]
+            .concat();
+            bytes.extend(encode_varint(1));
+            bytes.push(PSBT_IN_WITNESS_UTXO);
+            bytes.extend(encode_varint(witness_utxo.len()));
+            bytes.extend(witness_utxo);
+            bytes.push(0x00);
+        }
+        for _ in &self.outputs {
+            bytes.push(0x00);
+        }
+        bytes
+    }
+}
+
+// OP_RETURN "BTC2" || height (u64 big endian) || block hash
+pub fn commitment_script(block_number: i64, block_hash: [u8; 32]) -> Vec<u8> {
+    let data = [
+        &ANCHOR_MAGIC[..],
+        &(block_number as u64).to_be_bytes(),
+        &block_hash,
+    ]
+    .concat();
+    [vec![OP_RETURN, data.len() as u8], data].concat()
+}
+
+// Spends the largest UTXOs first and returns any change to the first one's script
+pub fn build_transaction(
+    commitment: Vec<u8>,
+    mut utxos: Vec<Utxo>,
+    fee_rate: u64,
+) -> Result<UnsignedTransaction> {
+    utxos.sort_by(|a, b| b.value.cmp(&a.value));
+    let mut inputs = vec![];
+    let mut total = 0;
+    for utxo in utxos {
+        total += utxo.value;
+        inputs.push(utxo);
+        let fee = fee_rate
+            * (OVERHEAD_VSIZE
+                + INPUT_VSIZE * inputs.len() as u64
+                + COMMITMENT_OUTPUT_VSIZE
+                + CHANGE_OUTPUT_VSIZE);
+        if total >= fee {
+            let mut outputs = vec![(0, commitment)];
+            if total - fee >= DUST_LIMIT {
+                outputs.push((total - fee, inputs[0].script_pubkey.clone()));
+            }
+            return Ok(UnsignedTransaction { inputs, outputs });
+        }
+    }
+
+    Err(Error::Error(
+        "Insufficient funds in the anchor descriptor".to_string(),
+    ))
+}
+
+pub async fn start(pool: PgPool, bitcoind_url: String, descriptor: String) -> Result<()> {
+    let mut ticker = time::interval(POLL_INTERVAL);
+
+    // bitcoind being unreachable or the descriptor running dry is retried on the next tick
+    loop {
+        ticker.tick().await;
+        if let Err(err) = ingest_confirmations(&pool, &bitcoind_url).await {
+            tracing::error!(error = %err, "ingesting anchor confirmations failed");
+        }
+        if let Err(err) = anchor_if_due(&pool, &bitcoind_url, &descriptor).await {
+            tracing::error!(error = %err, "anchoring failed");
+        }
+    }
+}
+
+async fn anchor_if_due(pool: &PgPool, bitcoind_url: &str, descriptor: &str) -> Result<()> {
+    let Some(head) = get_head_block(pool, i64::MAX).await? else {
+        return Ok(());
+    };
+    let last_anchored = get_last_anchored_block_number(pool)
+        .await?
+        .unwrap_or(LAST_LEGACY_BLOCK_NUMBER);
+    if head.number - last_anchored < *ANCHOR_INTERVAL {
+        return Ok(());
+    }
+
+    // Outputs funding anchors that have not confirmed yet are still unspent on chain.
+    // An anchor that was never broadcast gives them back after the timeout.
+    let mut reserved: HashSet<String> = HashSet::new();
+    for (funding_outpoints, expired) in
+        get_anchor_reservations(pool, *ANCHOR_RESERVATION_TIMEOUT).await?
+    {
+        let broadcast = mempool_spender(bitcoind_url, &funding_outpoints).await?;
+        if !expired || broadcast.is_some() {
+            reserved.extend(funding_outpoints);
+        }
+    }
+    let utxos: Vec<Utxo> = serde_json::from_value(
+        client::call(
+            bitcoind_url,
+            "scantxoutset",
+            json!(["start", [{ "desc": descriptor }]]),
+        )
+        .await?
+        .get_mut("unspents")
+        .map(Value::take)
+        .unwrap_or(json!([])),
+    )
+    .map_err(|e| Error::ParseError(e.to_string()))?;
+    let transaction = build_transaction(
+        commitment_script(head.number, head.hash),
+        utxos
+            .into_iter()
+            .filter(|utxo| !reserved.contains(&utxo.outpoint()))
+            .collect(),
+        *ANCHOR_FEE_RATE,
+    )?;
+
+    insert_anchor(
+        pool,
+        &Anchor {
+            block_number: head.number,
+            block_hash: head.hash,
+            txid: transaction.txid(),
+            psbt: transaction.psbt(),
+            funding_outpoints: transaction.inputs.iter().map(Utxo::outpoint).collect(),
+            bitcoin_block_hash: None,
+            bitcoin_block_height: None,
+        },
+    )
+    .await
+}
+
+// An anchor that can't be checked doesn't hold up the others
+async fn ingest_confirmations(pool: &PgPool, bitcoind_url: &str) -> Result<()> {
+    for anchor in get_anchors(pool, true).await? {
+        if let Err(err) = ingest_confirmation(pool, bitcoind_url, &anchor).await {
+            tracing::warn!(txid = hex::encode(anchor.txid), error = %err, "checking anchor failed");
+        }
+    }
+
+    Ok(())
+}
+
+// Anchors bitcoind does not know about yet are left pending. A fee bump changes the
+// txid, so the anchor follows whichever transaction spends its funding outputs.
+async fn ingest_confirmation(pool: &PgPool, bitcoind_url: &str, anchor: &Anchor) -> Result<()> {
+    let mut txid = anchor.txid;
+    if let Some(spender) = mempool_spender(bitcoind_url, &anchor.funding_outpoints).await? {
+        if spender != anchor.txid && commits_to(bitcoind_url, spender, anchor).await? {
+            replace_anchor_txid(pool, anchor.txid, spender).await?;
+            txid = spender;
+        }
+    }
+    let Ok(transaction) = client::call(
+        bitcoind_url,
+        "getrawtransaction",
+        json!([hex::encode(txid), true]),
+    )
+    .await
+    else {
+        return Ok(());
+    };
+    let Some(block_hash) = transaction.get("blockhash").and_then(Value::as_str) else {
+        return Ok(());
+    };
+    let header = client::call(bitcoind_url, "getblockheader", json!([block_hash])).await?;
+    let height = header
+        .get("height")
+        .and_then(Value::as_i64)
+        .ok_or(Error::ParseError("Expected block height".to_string()))?;
+
+    confirm_anchor(pool, txid, hex::decode(block_hash)?.try_into()?, height).await
+}
+
+// `gettxspendingprevout` only sees the mempool, so this is `None` once the spend confirms
+async fn mempool_spender(
+    bitcoind_url: &str,
+    funding_outpoints: &[String],
+) -> Result<Option<[u8; 32]>> {
+    let prevouts = funding_outpoints
+        .iter()
+        .map(|outpoint| {
+            let (txid, vout) = outpoint
+                .split_once(':')
+                .ok_or(Error::ParseError(format!("Invalid outpoint {}", outpoint)))?;
+            let vout: u32 = vout
+                .parse()
+                .map_err(|_| Error::ParseError(format!("Invalid outpoint {}", outpoint)))?;
+            Ok(json!({ "txid": txid, "vout": vout }))
+        })
+        .collect::<Result<Vec<Value>>>()?;
+    let spends = client::call(bitcoind_url, "gettxspendingprevout", json!([prevouts])).await?;
+
+    spends
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find_map(|spend| spend.get("spendingtxid").and_then(Value::as_str))
+        .map(|txid| Ok(hex::decode(txid)?.try_into()?))
+        .transpose()
+}
+
+async fn commits_to(bitcoind_url: &str, txid: [u8; 32], anchor: &Anchor) -> Result<bool> {
+    let transaction = client::call(
+        bitcoind_url,
+        "getrawtransaction",
+        json!([hex::encode(txid), true]),
+    )
+    .await?;
+    let commitment = hex::encode(commitment_script(anchor.block_number, anchor.block_hash));
+
+    Ok(transaction
+        .get("vout")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .any(|output| output["scriptPubKey"]["hex"] == commitment.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commitment_script() {
+        let script = super::commitment_script(840000, [1; 32]);
+
+        assert_eq!(script.len(), 46);
+        assert_eq!(script[..6], [OP_RETURN, 44, b'B', b'T', b'C', b'2']);
+        assert_eq!(script[6..14], 840000u64.to_be_bytes());
+        assert_eq!(script[14..], [1; 32]);
+    }
+
+    #[test]
+    fn build_transaction() {
+        let utxo = |value| Utxo {
+            txid: [value as u8; 32],
+            vout: 0,
+            script_pubkey: vec![0x00, 0x14],
+            value,
+        };
+        let transaction =
+            super::build_transaction(vec![OP_RETURN], vec![utxo(1000), utxo(100000)], 2).unwrap();
+
+        assert_eq!(transaction.inputs.len(), 1);
+        assert_eq!(transaction.inputs[0].value, 100000);
+        assert_eq!(transaction.outputs[1].0, 100000 - 2 * 177);
+    }
+
+    #[test]
+    fn psbt() {
+        let script_pubkey = [&[0x00, 0x14][..], &[0x11; 20]].concat();
+        let transaction = UnsignedTransaction {
+            inputs: vec![Utxo {
+                txid: hex_lit::hex!(
+                    "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+                ),
+                vout: 1,
+                script_pubkey: script_pubkey.clone(),
+                value: 100000,
+            }],
+            outputs: vec![(0, vec![OP_RETURN]), (99646, script_pubkey)],
+        };
+
+        assert_eq!(
+            transaction.txid(),
+            hex_lit::hex!("41748f468c8d9fd424e8813191147e4279e589c6ad45f511ef469a169d87f058")
+        );
+        assert_eq!(
+            hex::encode(transaction.psbt()),
+            [
+                // Magic, then the unsigned transaction as the only global
+                "70736274ff01005c",
+                "02000000011f1e1d1c1b1a191817161514131211100f0e0d0c0b0a090807060504030201000100000000fdffffff",
+                "020000000000000000016a3e850100000000001600141111111111111111111111111111111111111111",
+                "0000000000",
+                // The input's witness UTXO
+                "01011fa0860100000000001600141111111111111111111111111111111111111111",
+                "00",
+                // An empty map for each output
+                "0000",
+            ]
+            .concat()
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use crate::error::{Error, Result};
+use bech32::{hrp, segwit};
+
+const P2PKH_VERSION: u8 = 0x00;
+const P2SH_VERSION: u8 = 0x05;
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_DUP: u8 = 0x76;
+const OP_EQUAL: u8 = 0x87;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_HASH160: u8 = 0xa9;
+pub const OP_CHECKSIG: u8 = 0xac;
+
+pub fn p2pkh_script(hash: &[u8]) -> Vec<u8> {
+    [
+        &[OP_DUP, OP_HASH160, hash.len() as u8][..],
+        hash,
+        &[OP_EQUALVERIFY, OP_CHECKSIG],
+    ]
+    .concat()
+}
+
+pub fn p2sh_script(hash: &[u8]) -> Vec<u8> {
+    [&[OP_HASH160, hash.len() as u8][..], hash, &[OP_EQUAL]].concat()
+}
+
+pub fn witness_script(version: u8, program: &[u8]) -> Vec<u8> {
+    let op = if version == 0 {
+        OP_0
+    } else {
+        OP_1 + version - 1
+    };
+    [&[op, program.len() as u8][..], program].concat()
+}
+
+// Only mainnet addresses since the legacy snapshot was taken from mainnet
+pub fn script_pubkey(address: &str) -> Result<Vec<u8>> {
+    if let Ok((address_hrp, version, program)) = segwit::decode(address) {
+        if address_hrp != hrp::BC {
+            return Err(Error::ParseError(format!(
+                "{} is not a mainnet address",
+                address
+            )));
+        }
+        return Ok(witness_script(version.to_u8(), &program));
+    }
+
+    let payload = bs58::decode(address)
+        .with_check(None)
+        .into_vec()
+        .map_err(|_| Error::ParseError(format!("Invalid address {}", address)))?;
+    match payload.as_slice() {
+        [P2PKH_VERSION, hash @ ..] if hash.len() == 20 => Ok(p2pkh_script(hash)),
+        [P2SH_VERSION, hash @ ..] if hash.len() == 20 => Ok(p2sh_script(hash)),
+        _ => Err(Error::ParseError(format!(
+            "Unsupported address {}",
+            address
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_lit::hex;
+
+    #[test]
+    fn script_pubkey() {
+        for (address, script_pubkey) in [
+            (
+                "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+                &hex!("76a91477bff20c60e522dfaa3350c39b030a5d004e839a88ac")[..],
+            ),
+            (
+                "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+                &hex!("a914b472a266d0bd89c13706a4132ccfb16f7c3b9fcb87"),
+            ),
+            (
+                "bc1qw508d6qejxtdg4c3zdgtmm7z5r3yjhzvrggld4",
+                &hex!("0014751e76e8199196d454941c45d1b3a323f1433bd6"),
+            ),
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                &hex!("512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"),
+            ),
+        ] {
+            assert_eq!(super::script_pubkey(address).unwrap(), script_pubkey);
+        }
+
+        assert!(super::script_pubkey("tb1qw508d6qejxtdg4c3zdgtmm7z5r3yjhzvrggld4").is_err());
+        assert!(super::script_pubkey("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3").is_err());
+    }
+}
@@ -1,4 +1,5 @@
-mod sha256d;
+pub mod address;
+pub mod sha256d;
 pub mod utxos;
 
 use digest::Digest;
@@ -48,7 +49,7 @@ fn signed_message_digest(message: Vec<u8>) -> Sha256d {
 }
 
 // https://github.com/bitcoin/bitcoin/blob/c8e3978114716bb8fb10695b9d187652f3ab4926/src/leveldb/util/coding.cc#L21
-fn encode_varint(value: usize) -> Vec<u8> {
+pub fn encode_varint(value: usize) -> Vec<u8> {
     match value {
         0..=0xfc => vec![value as u8],
         0xfd..=0xffff => [&[0xfd], &(value as u16).to_le_bytes()[..]].concat(),
@@ -1,21 +1,271 @@
-use super::decode_varint;
+use super::{
+    address::{p2pkh_script, p2sh_script, OP_CHECKSIG},
+    decode_varint,
+};
 
 use digest::Digest;
 
-use crate::error::{Error, Result};
+use crate::{
+    db,
+    error::{Error, Result},
+};
+use k256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey};
 use ripemd::Ripemd160;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
 use sha2::Sha256;
+use sqlx::PgPool;
 use std::io::Cursor;
+use tokio::task::spawn_blocking;
 
+const UTXOS_PATH: &str = "utxos.sqlite";
+const INDEX_BATCH_SIZE: i64 = 10_000;
+
+// `hash` is in internal byte order, the reverse of how txids are displayed
 #[derive(Debug)]
 pub struct Outpoint {
     pub hash: [u8; 32],
     pub index: u16,
 }
 
-pub fn validate(vout: &Outpoint, unlocking_script: &[u8]) -> Result<i64> {
-    let conn = Connection::open("utxos.sqlite").unwrap();
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptType {
+    P2pkh,
+    P2sh,
+    P2pk,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+    Nonstandard,
+}
+
+impl ScriptType {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::P2pkh => "p2pkh",
+            Self::P2sh => "p2sh",
+            Self::P2pk => "p2pk",
+            Self::P2wpkh => "p2wpkh",
+            Self::P2wsh => "p2wsh",
+            Self::P2tr => "p2tr",
+            Self::Nonstandard => "nonstandard",
+        }
+    }
+
+    // Only the scripts `validate_compressed_script` can unlock
+    pub fn claimable(self) -> bool {
+        self == Self::P2wpkh
+    }
+}
+
+#[derive(Debug)]
+pub struct LegacyOutput {
+    pub amount: i64,
+    pub script_type: ScriptType,
+}
+
+pub fn get(outpoint: &Outpoint) -> Result<Option<LegacyOutput>> {
+    Ok(get_many(std::slice::from_ref(outpoint))?.remove(0))
+}
+
+// Shares one connection across lookups
+pub fn get_many(outpoints: &[Outpoint]) -> Result<Vec<Option<LegacyOutput>>> {
+    let conn = open()?;
+    let mut statement = conn.prepare(
+        "SELECT amount, compressed_script from utxos WHERE transaction_id = ? AND vout = ?",
+    )?;
+
+    outpoints
+        .iter()
+        .map(|outpoint| {
+            let Some((amount, compressed_script)) = statement
+                .query_row(params![outpoint.hash, outpoint.index], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .optional()?
+            else {
+                return Ok(None);
+            };
+
+            Ok(Some(LegacyOutput {
+                amount,
+                script_type: script_type(&compressed_script)?,
+            }))
+        })
+        .collect()
+}
+
+// https://github.com/bitcoin/bitcoin/blob/4cc99df44aec4d104590aee46cf18318e22a8568/src/compressor.h#L24-L47
+fn split_compressed_script(compressed_script: &[u8]) -> Result<(u64, &[u8])> {
+    let mut cursor = Cursor::new(compressed_script);
+    let nsize = decode_varint(&mut cursor).map_err(|e| Error::Error(e.to_string()))?;
+
+    Ok((nsize, &compressed_script[cursor.position() as usize..]))
+}
+
+pub fn script_type(compressed_script: &[u8]) -> Result<ScriptType> {
+    Ok(match split_compressed_script(compressed_script)? {
+        (0, _) => ScriptType::P2pkh,
+        (1, _) => ScriptType::P2sh,
+        (2..=5, _) => ScriptType::P2pk,
+        (_, script) => classify(script),
+    })
+}
+
+pub fn classify(script_pubkey: &[u8]) -> ScriptType {
+    match script_pubkey {
+        [0x76, 0xa9, 0x14, .., 0x88, 0xac] if script_pubkey.len() == 25 => ScriptType::P2pkh,
+        [0xa9, 0x14, .., 0x87] if script_pubkey.len() == 23 => ScriptType::P2sh,
+        [0x00, 0x14, hash @ ..] if hash.len() == 20 => ScriptType::P2wpkh,
+        [0x00, 0x20, hash @ ..] if hash.len() == 32 => ScriptType::P2wsh,
+        [0x51, 0x20, key @ ..] if key.len() == 32 => ScriptType::P2tr,
+        _ => ScriptType::Nonstandard,
+    }
+}
+
+pub fn decompress_script(compressed_script: &[u8]) -> Result<Vec<u8>> {
+    Ok(match split_compressed_script(compressed_script)? {
+        (0, hash) => p2pkh_script(hash),
+        (1, hash) => p2sh_script(hash),
+        (2 | 3, x) => [&[33][..], &[compressed_script[0]], x, &[OP_CHECKSIG]].concat(),
+        (4 | 5, x) => {
+            let public_key =
+                PublicKey::from_sec1_bytes(&[&[compressed_script[0] - 2][..], x].concat())
+                    .map_err(|_| Error::InvalidScript)?;
+            [
+                &[65][..],
+                public_key.to_encoded_point(false).as_bytes(),
+                &[OP_CHECKSIG],
+            ]
+            .concat()
+        }
+        (_, script) => script.to_vec(),
+    })
+}
+
+// Electrum style, used to index the snapshot by address
+pub fn script_hash(script_pubkey: &[u8]) -> [u8; 32] {
+    Sha256::digest(script_pubkey).into()
+}
+
+fn open() -> Result<Connection> {
+    Ok(Connection::open(UTXOS_PATH)?)
+}
+
+// Unlike `open` this doesn't create an empty store when the file is missing
+pub fn ping() -> Result<()> {
+    Connection::open_with_flags(UTXOS_PATH, OpenFlags::SQLITE_OPEN_READ_ONLY)?
+        .prepare("SELECT 1 FROM utxos LIMIT 1")?;
+
+    Ok(())
+}
+
+fn indexed_by_script_hash(conn: &Connection) -> Result<bool> {
+    Ok(conn
+        .prepare("SELECT 1 FROM pragma_table_info('utxos') WHERE name = 'script_hash'")?
+        .exists([])?)
+}
+
+// Adds and fills the `script_hash` column, safe to re-run after an interruption.
+// Each batch is read in full before it's updated and committed on its own, so
+// no statement is left stepping over rows that are being written.
+pub fn index_script_hashes() -> Result<usize> {
+    let mut conn = open()?;
+    if !indexed_by_script_hash(&conn)? {
+        conn.execute("ALTER TABLE utxos ADD COLUMN script_hash BLOB", [])?;
+    }
+
+    let mut count = 0;
+    let mut last_rowid = 0;
+    loop {
+        let tx = conn.transaction()?;
+        let batch = tx
+            .prepare(
+                "SELECT rowid, compressed_script FROM utxos
+                WHERE rowid > ? AND script_hash IS NULL ORDER BY rowid LIMIT ?",
+            )?
+            .query_map(params![last_rowid, INDEX_BATCH_SIZE], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let Some((rowid, _)) = batch.last() else {
+            break;
+        };
+        last_rowid = *rowid;
+        {
+            let mut update = tx.prepare("UPDATE utxos SET script_hash = ? WHERE rowid = ?")?;
+            for (rowid, compressed_script) in &batch {
+                update.execute(params![
+                    script_hash(&decompress_script(compressed_script)?),
+                    rowid
+                ])?;
+            }
+        }
+        tx.commit()?;
+        count += batch.len();
+    }
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS utxos_script_hash ON utxos (script_hash)",
+        [],
+    )?;
+
+    Ok(count)
+}
+
+// Outputs spent before script types and amounts were recorded, or imported
+// from a snapshot, are looked up once so the upgrade stats only read Postgres
+pub async fn fill_spent_output_details(pool: &PgPool) -> Result<usize> {
+    let missing = db::get_spent_legacy_outputs_without_details(pool).await?;
+    let outpoints = missing
+        .iter()
+        .map(|(_, hash, index)| {
+            let mut hash = *hash;
+            hash.reverse();
+            Ok(Outpoint {
+                hash,
+                index: u16::try_from(*index)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let outputs = spawn_blocking(move || get_many(&outpoints)).await??;
+    let mut count = 0;
+    for ((id, _, _), output) in missing.iter().zip(outputs) {
+        if let Some(output) = output {
+            db::set_spent_legacy_output_details(pool, *id, &output).await?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+// Requires `index_script_hashes` to have been run
+pub fn get_by_script_pubkey(script_pubkey: &[u8]) -> Result<Vec<(Outpoint, i64)>> {
+    let conn = open()?;
+    if !indexed_by_script_hash(&conn)? {
+        return Err(Error::Error(
+            "Legacy outputs are not indexed by address, run `bitcoin2 index-legacy-utxos`"
+                .to_string(),
+        ));
+    }
+    let mut statement =
+        conn.prepare("SELECT transaction_id, vout, amount FROM utxos WHERE script_hash = ?")?;
+    let outputs = statement
+        .query_map(params![script_hash(script_pubkey)], |row| {
+            Ok((
+                Outpoint {
+                    hash: row.get(0)?,
+                    index: row.get(1)?,
+                },
+                row.get(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(outputs)
+}
+
+pub fn validate(vout: &Outpoint, unlocking_script: &[u8]) -> Result<LegacyOutput> {
+    let conn = Connection::open(UTXOS_PATH).unwrap();
     let sql = "SELECT amount, compressed_script from utxos WHERE transaction_id = ? AND vout = ?";
     let (amount, compressed_script): (Result<i64>, Result<Vec<u8>>) = conn
         .query_row(sql, params![vout.hash, vout.index], |row| {
@@ -25,8 +275,13 @@ pub fn validate(vout: &Outpoint, unlocking_script: &[u8]) -> Result<i64> {
             ))
         })
         .map_err(|e| Error::Error(e.to_string()))?;
-    validate_compressed_script(compressed_script?, unlocking_script)?;
-    Ok(amount?)
+    let compressed_script = compressed_script?;
+    let script_type = script_type(&compressed_script)?;
+    validate_compressed_script(compressed_script, unlocking_script)?;
+    Ok(LegacyOutput {
+        amount: amount?,
+        script_type,
+    })
 }
 
 pub fn validate_compressed_script(
@@ -50,3 +305,44 @@ pub fn validate_p2wpkh(compressed_script: Vec<u8>, unlocking_script: &[u8]) -> R
         Err(Error::InvalidScript)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_type() {
+        assert_eq!(super::script_type(&[0; 21]).unwrap(), ScriptType::P2pkh);
+        assert_eq!(super::script_type(&[1; 21]).unwrap(), ScriptType::P2sh);
+        assert_eq!(super::script_type(&[2; 33]).unwrap(), ScriptType::P2pk);
+        assert_eq!(
+            super::script_type(&[&[28, 0x00, 0x14][..], &[0; 20]].concat()).unwrap(),
+            ScriptType::P2wpkh
+        );
+        assert_eq!(
+            super::script_type(&[&[40, 0x51, 0x20][..], &[0; 32]].concat()).unwrap(),
+            ScriptType::P2tr
+        );
+        assert_eq!(
+            super::script_type(&[7, 0x6a]).unwrap(),
+            ScriptType::Nonstandard
+        );
+    }
+
+    #[test]
+    fn decompress_script() {
+        let hash = [1; 20];
+        let p2wpkh = [&[0x00, 0x14][..], &hash].concat();
+
+        assert_eq!(
+            super::decompress_script(&[&[0][..], &hash].concat()).unwrap(),
+            p2pkh_script(&hash)
+        );
+        assert_eq!(
+            super::decompress_script(&[&[28][..], &p2wpkh].concat()).unwrap(),
+            p2wpkh
+        );
+        assert_eq!(classify(&p2pkh_script(&hash)), ScriptType::P2pkh);
+        assert_eq!(classify(&p2sh_script(&hash)), ScriptType::P2sh);
+    }
+}
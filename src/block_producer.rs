@@ -1,9 +1,13 @@
 use crate::{
+    chain_head::ChainHead,
+    control,
     db::{
-        get_last_block_timestamp, get_transactions_by_block_number, insert_block,
+        get_head_block, get_last_block_timestamp, insert_block, lock_pending_transactions,
         update_transactions_block_number,
     },
     error::Result,
+    events::{self, Event},
+    health, metrics, sequencer,
 };
 use digest::Digest;
 use reth_primitives::TransactionSigned;
@@ -17,9 +21,9 @@ use tokio::{
     time::{sleep_until, Duration, Instant},
 };
 
-const BLOCK_TIME: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+pub const BLOCK_TIME: tokio::time::Duration = tokio::time::Duration::from_secs(1);
 
-pub async fn start(pool: PgPool) -> Result<()> {
+pub async fn start(pool: PgPool, chain_head: ChainHead) -> Result<()> {
     let last_block_timestamp = get_last_block_timestamp(&pool).await?;
     let next_block_timestamp = datetime_to_tokio_instant(last_block_timestamp) + BLOCK_TIME;
 
@@ -28,28 +32,56 @@ pub async fn start(pool: PgPool) -> Result<()> {
 
     loop {
         ticker.tick().await;
-        add_block(pool.clone()).await?;
+        let block_policy = control::block_policy();
+        if ticker.period() != block_policy.block_time {
+            ticker = time::interval_at(
+                Instant::now() + block_policy.block_time,
+                block_policy.block_time,
+            );
+        }
+        if !control::production_paused() {
+            // The transactions stay pending, so a failed block is retried on the next tick
+            if let Err(err) = add_block(pool.clone(), &chain_head).await {
+                tracing::error!(error = %err, "block production failed");
+                continue;
+            }
+        }
+        health::record_slot();
     }
 }
 
-async fn add_block(pool: PgPool) -> Result<()> {
+#[tracing::instrument(skip_all)]
+async fn add_block(pool: PgPool, chain_head: &ChainHead) -> Result<()> {
+    let started_at = Instant::now();
     let mut tx = pool.clone().begin().await?;
-    let proposed_transactions = get_transactions_by_block_number(&mut *tx, None).await?;
+    // Locked, since transactions can be dropped from the admin port
+    let proposed_transactions =
+        lock_pending_transactions(&mut *tx, control::block_policy().max_transactions).await?;
     if proposed_transactions.len() == 0 {
         return Ok(());
     }
     let transaction_ids: Vec<i64> = proposed_transactions.iter().map(|t| t.0).collect();
-    let block_number = insert_block(
-        &mut *tx,
-        block_hash(proposed_transactions.into_iter().map(|t| t.1).collect()),
-    )
-    .await?;
-    update_transactions_block_number(&mut *tx, transaction_ids, block_number).await?;
+    let transaction_count = transaction_ids.len();
+    let parent_hash = get_head_block(&mut *tx, i64::MAX)
+        .await?
+        .map(|block| block.hash)
+        .unwrap_or_default();
+    let hash = block_hash(proposed_transactions.into_iter().map(|t| t.1).collect());
+    let block = insert_block(&mut *tx, hash, sequencer::sign(&hash)?).await?;
+    update_transactions_block_number(&mut *tx, transaction_ids, block.number).await?;
     tx.commit().await?;
+    metrics::record_block(started_at.elapsed(), transaction_count);
+    tracing::info!(
+        number = block.number,
+        transactions = transaction_count,
+        "produced block"
+    );
+    chain_head.set(block.clone());
+    events::publish(Event::NewHead { block, parent_hash });
     Ok(())
 }
 
-fn block_hash(signed_transations: Vec<TransactionSigned>) -> [u8; 32] {
+pub fn block_hash(signed_transations: Vec<TransactionSigned>) -> [u8; 32] {
     let mut hasher = Sha256::new();
     for signed_transaction in signed_transations.iter() {
         hasher.update(&signed_transaction.hash())
@@ -79,11 +111,15 @@ fn datetime_to_tokio_instant(datetime: PrimitiveDateTime) -> Instant {
 
 #[cfg(test)]
 mod tests {
-    use crate::{app, constants::LAST_LEGACY_BLOCK_NUMBER, db::get_last_block_number, evm::Evm};
+    use crate::{
+        app, chain_head::ChainHead, constants::LAST_LEGACY_BLOCK_NUMBER, db::get_last_block_number,
+        evm::Evm,
+    };
     use axum::{
         body::Body,
         http::{Request, StatusCode},
     };
+    use http_body_util::BodyExt;
     use serde_json::json;
     use sqlx::PgPool;
     use tower::ServiceExt;
@@ -113,11 +149,63 @@ mod tests {
         let response = app(pool.clone()).await.oneshot(request).await.unwrap();
 
         assert_eq!(response.status(), StatusCode::OK);
-        super::add_block(pool.clone()).await.unwrap();
+        let chain_head = ChainHead::load(&pool).await.unwrap();
+        super::add_block(pool.clone(), &chain_head).await.unwrap();
         assert_eq!(
             get_last_block_number(&pool.clone()).await.unwrap(),
             LAST_LEGACY_BLOCK_NUMBER + 1
         );
+        assert_eq!(chain_head.block_number(), LAST_LEGACY_BLOCK_NUMBER + 1);
+
+        let message = json!({
+                "jsonrpc": "2.0",
+                "method": "eth_getBlockByNumber",
+                "params": ["latest", true],
+                "id":1
+        });
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/json")
+            .uri("/")
+            .body(Body::from(message.to_string()))
+            .unwrap();
+
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let block: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let block = &block["result"];
+        assert_eq!(
+            block["number"],
+            format!("0x{:x}", LAST_LEGACY_BLOCK_NUMBER + 1)
+        );
+        assert_eq!(block["transactions"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            block["transactions"][0]["from"],
+            "0xf204ee5596cabc6ec60e5e92fd412ea7f856b625"
+        );
+        assert_eq!(block["transactions"][0]["blockHash"], block["hash"]);
+        assert_eq!(block["transactions"][0]["transactionIndex"], "0x0");
+        assert_eq!(block["gasUsed"], "0x5208");
+
+        let message = json!({
+                "jsonrpc": "2.0",
+                "method": "eth_getTransactionReceipt",
+                "params": [block["transactions"][0]["hash"]],
+                "id":1
+        });
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/json")
+            .uri("/")
+            .body(Body::from(message.to_string()))
+            .unwrap();
+
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let receipt: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let receipt = &receipt["result"];
+        assert_eq!(receipt["gasUsed"], "0x5208");
+        assert_eq!(receipt["cumulativeGasUsed"], "0x5208");
         Ok(())
     }
 }
@@ -0,0 +1,61 @@
+use crate::{
+    constants::LAST_LEGACY_BLOCK_NUMBER,
+    db::{get_head_block, Block},
+    error::Result,
+};
+use sqlx::PgPool;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncStatus {
+    pub starting_block: i64,
+    pub highest_block: i64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    block: Option<Block>,
+    // Only set while a follower is behind its primary
+    sync_status: Option<SyncStatus>,
+}
+
+// Loaded once per router, after that `block_producer` or `follower` keep their
+// clone of it current
+#[derive(Debug, Clone, Default)]
+pub struct ChainHead(Arc<RwLock<State>>);
+
+impl ChainHead {
+    pub async fn load(pool: &PgPool) -> Result<Self> {
+        let chain_head = Self::default();
+        chain_head.0.write().unwrap().block = get_head_block(pool, i64::MAX).await?;
+
+        Ok(chain_head)
+    }
+
+    pub fn set(&self, block: Block) {
+        self.0.write().unwrap().block = Some(block);
+    }
+
+    pub fn block(&self) -> Option<Block> {
+        self.0.read().unwrap().block.clone()
+    }
+
+    // The legacy chain's height until the first block is produced
+    pub fn block_number(&self) -> i64 {
+        self.0
+            .read()
+            .unwrap()
+            .block
+            .as_ref()
+            .map(|block| block.number)
+            .unwrap_or(LAST_LEGACY_BLOCK_NUMBER)
+    }
+
+    pub fn set_sync_status(&self, sync_status: Option<SyncStatus>) {
+        self.0.write().unwrap().sync_status = sync_status;
+    }
+
+    pub fn sync_status(&self) -> Option<SyncStatus> {
+        self.0.read().unwrap().sync_status
+    }
+}
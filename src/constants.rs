@@ -1,7 +1,12 @@
+use k256::ecdsa::{SigningKey, VerifyingKey};
 use lazy_static::lazy_static;
 use sqlx::{migrate::Migrator, types::time::PrimitiveDateTime};
 
-use std::env;
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, Ipv4Addr},
+};
 use time::macros::datetime;
 
 macro_rules! account_id {
@@ -12,6 +17,8 @@ macro_rules! account_id {
     }};
 }
 pub const LEGACY_ACCOUNT: [u8; 20] = account_id!(0);
+// Seeded onto LEGACY_ACCOUNT by the seed_legacy_data migration
+pub const LEGACY_SUPPLY: i64 = 196329751666;
 pub const DEFAULT_GAS_LIMIT: i64 = 21000;
 pub const LAST_LEGACY_BLOCK_TIMESTAMP: PrimitiveDateTime = datetime!(2024-04-19 08:05:33);
 pub const LAST_LEGACY_BLOCK_NUMBER: i64 = 839999;
@@ -50,6 +57,65 @@ lazy_static! {
             .collect::<Vec<String>>()
             .clone()))
         .unwrap_or(vec![]);
+    // Cost units per minute for each IP, see `rate_limit::cost`, 0 disables the limit
+    pub static ref RATE_LIMIT: u64 = env::var("RATE_LIMIT")
+        .and_then(|limit| Ok(limit.parse().unwrap_or(1200)))
+        .unwrap_or(1200);
+    // Comma separated `key:limit` pairs, clients send the key in an X-API-Key header
+    pub static ref API_KEYS: HashMap<String, u64> = env::var("API_KEYS")
+        .map(|keys| keys
+            .split(",")
+            .filter_map(|key| key.split_once(":"))
+            .map(|(key, limit)| (key.to_string(), limit.parse().unwrap_or(*RATE_LIMIT)))
+            .collect())
+        .unwrap_or_default();
+    // Bytes
+    pub static ref MAX_BODY_SIZE: usize = env::var("MAX_BODY_SIZE")
+        .and_then(|size| Ok(size.parse().unwrap_or(1 << 20)))
+        .unwrap_or(1 << 20);
+    // The admin namespace is only served when a port and a token or JWT secret are set
+    pub static ref ADMIN_PORT: Option<u16> = env::var("ADMIN_PORT")
+        .ok()
+        .map(|port| port.parse().unwrap());
+    // The admin port is plain HTTP, so it only listens on loopback unless told otherwise
+    pub static ref ADMIN_BIND: IpAddr = env::var("ADMIN_BIND")
+        .map(|ip| ip.parse().unwrap())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    pub static ref ADMIN_TOKEN: Option<String> = env::var("ADMIN_TOKEN").ok();
+    // HS256
+    pub static ref ADMIN_JWT_SECRET: Option<String> = env::var("ADMIN_JWT_SECRET").ok();
+    pub static ref SNAPSHOT_DIR: String =
+        env::var("SNAPSHOT_DIR").unwrap_or("snapshots".to_string());
+    // Unix domain socket for local tooling, only served when set
+    pub static ref IPC_PATH: Option<String> = env::var("IPC_PATH")
+        .ok()
+        .filter(|path| !path.is_empty());
+    pub static ref MAX_BATCH_SIZE: usize = env::var("MAX_BATCH_SIZE")
+        .and_then(|size| Ok(size.parse().unwrap_or(100)))
+        .unwrap_or(100);
+    // Set on followers, which sync from and forward writes to this primary
+    pub static ref PRIMARY_URL: Option<String> = env::var("PRIMARY_URL").ok();
+    // bitcoind (or a stand-in) used to fund and track anchors, credentials go in the URL
+    pub static ref BITCOIND_URL: Option<String> = env::var("BITCOIND_URL").ok();
+    pub static ref ANCHOR_DESCRIPTOR: Option<String> = env::var("ANCHOR_DESCRIPTOR").ok();
+    pub static ref ANCHOR_INTERVAL: i64 = env::var("ANCHOR_INTERVAL")
+        .and_then(|interval| Ok(interval.parse().unwrap_or(100)))
+        .unwrap_or(100);
+    // Seconds an unconfirmed anchor's funding outputs are kept for it, after
+    // that only while a transaction spending them is in bitcoind's mempool
+    pub static ref ANCHOR_RESERVATION_TIMEOUT: i64 = env::var("ANCHOR_RESERVATION_TIMEOUT")
+        .and_then(|timeout| Ok(timeout.parse().unwrap_or(86400)))
+        .unwrap_or(86400);
+    // sat/vB
+    pub static ref ANCHOR_FEE_RATE: u64 = env::var("ANCHOR_FEE_RATE")
+        .and_then(|fee_rate| Ok(fee_rate.parse().unwrap_or(2)))
+        .unwrap_or(2);
+    pub static ref SEQUENCER_KEY: Option<SigningKey> = env::var("SEQUENCER_KEY")
+        .ok()
+        .map(|key| SigningKey::from_slice(&hex::decode(key).unwrap()).unwrap());
+    pub static ref SEQUENCER_PUBLIC_KEY: Option<VerifyingKey> = env::var("SEQUENCER_PUBLIC_KEY")
+        .ok()
+        .map(|key| VerifyingKey::from_sec1_bytes(&hex::decode(key).unwrap()).unwrap());
 }
 
 const _LEGACY_ACCOUNT: [u8; 20] = account_id!(0x00);
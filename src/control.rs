@@ -0,0 +1,58 @@
+use crate::block_producer::BLOCK_TIME;
+use lazy_static::lazy_static;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+
+// Runtime switches for operating the node, set through the admin namespace
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockPolicy {
+    pub block_time: Duration,
+    // Pending transactions beyond this wait for the next block
+    pub max_transactions: Option<usize>,
+}
+
+impl Default for BlockPolicy {
+    fn default() -> Self {
+        Self {
+            block_time: BLOCK_TIME,
+            max_transactions: None,
+        }
+    }
+}
+
+static INTAKE_PAUSED: AtomicBool = AtomicBool::new(false);
+static PRODUCTION_PAUSED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref BLOCK_POLICY: RwLock<BlockPolicy> = RwLock::new(BlockPolicy::default());
+}
+
+pub fn set_intake_paused(paused: bool) {
+    INTAKE_PAUSED.store(paused, Ordering::Relaxed);
+}
+
+pub fn intake_paused() -> bool {
+    INTAKE_PAUSED.load(Ordering::Relaxed)
+}
+
+pub fn set_production_paused(paused: bool) {
+    PRODUCTION_PAUSED.store(paused, Ordering::Relaxed);
+}
+
+pub fn production_paused() -> bool {
+    PRODUCTION_PAUSED.load(Ordering::Relaxed)
+}
+
+pub fn set_block_policy(block_policy: BlockPolicy) {
+    *BLOCK_POLICY.write().unwrap() = block_policy;
+}
+
+pub fn block_policy() -> BlockPolicy {
+    *BLOCK_POLICY.read().unwrap()
+}
@@ -1,4 +1,5 @@
 use crate::{
+    bitcoin_legacy::utxos::LegacyOutput,
     constants::{
         CHAIN_ID, DEFAULT_GAS_LIMIT, LAST_LEGACY_BLOCK_NUMBER, LAST_LEGACY_BLOCK_TIMESTAMP,
         LEGACY_ACCOUNT,
@@ -7,6 +8,7 @@ use crate::{
     evm::{scale_down, scale_up, upgrade_by_message::Outpoint, TransactionSigned},
 };
 use reth_primitives::{Address, Signature, TxKind, TxLegacy};
+use serde::Deserialize;
 pub use sqlx::FromRow;
 use sqlx::{
     postgres::PgRow, query, query_as, types::time::PrimitiveDateTime, Error::RowNotFound, Executor,
@@ -39,21 +41,28 @@ impl Transaction<'_> {
 
     pub async fn upgrade(
         &mut self,
-        inputs: Vec<Outpoint>,
+        inputs: Vec<(Outpoint, LegacyOutput)>,
         signer: [u8; 20],
-        value: i64,
     ) -> Result<()> {
-        for input in inputs {
-            self.insert_spent_legacy_output(input).await?
+        let value = inputs.iter().map(|(_, output)| output.amount).sum();
+        for (input, output) in inputs {
+            self.insert_spent_legacy_output(input, &output).await?
         }
         self.transfer(LEGACY_ACCOUNT, signer, value).await
     }
 
-    pub async fn insert_spent_legacy_output(&mut self, vout: Outpoint) -> Result<()> {
-        query("INSERT INTO spent_legacy_outputs (transaction_id, hash, index) VALUES ($1, $2, $3)")
+    // The output's script type and amount are kept so upgrade stats don't need the UTXO snapshot
+    pub async fn insert_spent_legacy_output(
+        &mut self,
+        vout: Outpoint,
+        output: &LegacyOutput,
+    ) -> Result<()> {
+        query("INSERT INTO spent_legacy_outputs (transaction_id, transaction_hash, hash, index, script_type, amount) VALUES ($1, (SELECT hash FROM transactions WHERE id = $1), $2, $3, $4, $5)")
             .bind(self.id)
             .bind(vout.hash)
             .bind(vout.index)
+            .bind(output.script_type.name())
+            .bind(output.amount)
             .execute(&mut *self.inner)
             .await?;
         Ok(())
@@ -81,10 +90,11 @@ pub async fn get_last_block_timestamp<'a, E>(pool: E) -> Result<PrimitiveDateTim
 where
     E: Executor<'a, Database = Postgres>,
 {
-    let result =
-        query_as::<_, (PrimitiveDateTime,)>("SELECT timestamp FROM blocks ORDER BY blocks.number")
-            .fetch_one(pool)
-            .await;
+    let result = query_as::<_, (PrimitiveDateTime,)>(
+        "SELECT timestamp FROM blocks ORDER BY blocks.number DESC LIMIT 1",
+    )
+    .fetch_one(pool)
+    .await;
     if matches!(result, Err(RowNotFound)) {
         return Ok(LAST_LEGACY_BLOCK_TIMESTAMP);
     };
@@ -96,9 +106,10 @@ pub async fn get_last_block_number<'a, E>(pool: E) -> Result<i64>
 where
     E: Executor<'a, Database = Postgres>,
 {
-    let result = query_as::<_, (i64,)>("select number from blocks order by blocks.number")
-        .fetch_one(pool)
-        .await;
+    let result =
+        query_as::<_, (i64,)>("select number from blocks order by blocks.number desc limit 1")
+            .fetch_one(pool)
+            .await;
     if matches!(result, Err(sqlx::Error::RowNotFound)) {
         return Ok(LAST_LEGACY_BLOCK_NUMBER);
     };
@@ -117,6 +128,20 @@ where
         .map(|row| row.get(0))?)
 }
 
+// Zero for addresses that have never been credited, where `get_balance` fails
+pub async fn get_balance_or_zero<'a, E>(pool: E, address: [u8; 20]) -> Result<i64>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (i64,)>(
+        "SELECT COALESCE((SELECT balance FROM accounts WHERE address = $1), 0)",
+    )
+    .bind(address)
+    .fetch_one(pool)
+    .await?
+    .0)
+}
+
 pub async fn get_transaction_by_id<'a, E>(
     pool: E,
     transaction_id: i64,
@@ -187,13 +212,200 @@ where
     } else {
         builder.push(" WHERE block_number IS NULL");
     }
+    builder.push(" ORDER BY transactions.id");
 
-    Ok(query_as(builder.sql()).fetch_all(pool).await?)
+    Ok(builder.build_query_as().fetch_all(pool).await?)
+}
+
+// Locks the rows it returns, so `drop_pending_transaction` waits for the block
+// they're put in and then finds them no longer pending
+pub async fn lock_pending_transactions<'a, E>(
+    e: E,
+    limit: Option<usize>,
+) -> Result<Vec<TransactionSignedRow>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let mut builder: QueryBuilder<'_, Postgres> = QueryBuilder::new(
+        "SELECT transactions.* FROM transactions WHERE block_number IS NULL ORDER BY transactions.id",
+    );
+    if let Some(limit) = limit {
+        builder.push(" LIMIT ");
+        builder.push_bind(i64::try_from(limit)?);
+    }
+    builder.push(" FOR UPDATE");
+
+    Ok(builder.build_query_as().fetch_all(e).await?)
+}
+
+pub struct StoredTransaction {
+    pub id: i64,
+    pub hash: [u8; 32],
+    pub signer: [u8; 20],
+    pub transaction: TransactionSigned,
+    // `None` until the transaction is included in a block
+    pub block_number: Option<i64>,
+    pub block_hash: Option<[u8; 32]>,
+    pub transaction_index: i64,
+}
+
+const SELECT_STORED_TRANSACTIONS: &str = "
+    SELECT transactions.*,
+    accounts.address AS signer,
+    blocks.hash AS block_hash,
+    (
+        SELECT COUNT(*) FROM transactions siblings
+        WHERE siblings.block_number = transactions.block_number
+        AND siblings.id < transactions.id
+    ) AS transaction_index
+    FROM transactions
+    JOIN accounts ON transactions.account_id = accounts.id
+    LEFT JOIN blocks ON transactions.block_number = blocks.number
+";
+
+impl FromRow<'_, PgRow> for StoredTransaction {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        let TransactionSignedRow(id, transaction) = TransactionSignedRow::from_row(row)?;
+        Ok(Self {
+            id,
+            hash: row.get::<Vec<u8>, _>("hash").try_into().unwrap(),
+            signer: row.get::<Vec<u8>, _>("signer").try_into().unwrap(),
+            transaction,
+            block_number: row.get("block_number"),
+            block_hash: row
+                .get::<Option<Vec<u8>>, _>("block_hash")
+                .map(|hash| hash.try_into().unwrap()),
+            transaction_index: row.get("transaction_index"),
+        })
+    }
+}
+
+pub async fn get_pending_stored_transactions<'a, E>(pool: E) -> Result<Vec<StoredTransaction>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(&format!(
+        "{SELECT_STORED_TRANSACTIONS} WHERE transactions.block_number IS NULL ORDER BY transactions.id"
+    ))
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn get_stored_transaction_by_hash<'a, E>(
+    pool: E,
+    hash: [u8; 32],
+) -> Result<Option<StoredTransaction>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(&format!(
+        "{SELECT_STORED_TRANSACTIONS} WHERE transactions.hash = $1"
+    ))
+    .bind(hash)
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn get_stored_transactions_by_block_number<'a, E>(
+    pool: E,
+    block_number: i64,
+) -> Result<Vec<StoredTransaction>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(&format!(
+        "{SELECT_STORED_TRANSACTIONS} WHERE transactions.block_number = $1 ORDER BY transactions.id"
+    ))
+    .bind(block_number)
+    .fetch_all(pool)
+    .await?)
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub number: i64,
+    pub hash: [u8; 32],
+    pub timestamp: PrimitiveDateTime,
+    pub signature: Option<[u8; 65]>,
+}
+
+impl FromRow<'_, PgRow> for Block {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            number: row.get("number"),
+            hash: row.get::<Vec<u8>, _>("hash").try_into().unwrap(),
+            timestamp: row.get("timestamp"),
+            signature: row
+                .get::<Option<Vec<u8>>, _>("signature")
+                .map(|signature| signature.try_into().unwrap()),
+        })
+    }
+}
+
+pub async fn get_blocks_after<'a, E>(
+    pool: E,
+    block_number: i64,
+    limit: Option<i64>,
+) -> Result<Vec<Block>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(
+        "SELECT number, hash, timestamp, signature FROM blocks WHERE number > $1 ORDER BY number LIMIT $2",
+    )
+    .bind(block_number)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn get_block_by_number<'a, E>(pool: E, block_number: i64) -> Result<Option<Block>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(
+        query_as("SELECT number, hash, timestamp, signature FROM blocks WHERE number = $1")
+            .bind(block_number)
+            .fetch_optional(pool)
+            .await?,
+    )
+}
+
+pub async fn get_first_block_number<'a, E>(pool: E) -> Result<Option<i64>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(
+        query_as::<_, (Option<i64>,)>("SELECT MIN(number) FROM blocks")
+            .fetch_one(pool)
+            .await?
+            .0,
+    )
+}
+
+pub async fn get_block_by_hash<'a, E>(pool: E, hash: [u8; 32]) -> Result<Option<Block>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(
+        query_as("SELECT number, hash, timestamp, signature FROM blocks WHERE hash = $1")
+            .bind(hash)
+            .fetch_optional(pool)
+            .await?,
+    )
 }
 
 pub struct TransactionSignedRow(pub i64, pub TransactionSigned);
 impl FromRow<'_, PgRow> for TransactionSignedRow {
     fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        if let Some(raw) = row.get::<Option<Vec<u8>>, _>("raw") {
+            return Ok(Self(
+                row.get::<i64, _>("id"),
+                TransactionSigned::decode_rlp_legacy_transaction(&mut &raw[..])
+                    .map_err(|e| sqlx::Error::Decode(e.to_string().into()))?,
+            ));
+        }
+        // Transactions stored before `raw` was added, rebuilt assuming the default gas limit and chain id
         let to = if let Some(to) = row.get::<Option<Vec<u8>>, _>("_to") {
             TxKind::Call(Address::new(to.try_into().unwrap()))
         } else {
@@ -219,6 +431,7 @@ impl FromRow<'_, PgRow> for TransactionSignedRow {
         ))
     }
 }
+#[derive(Debug, Clone, PartialEq)]
 pub struct LedgerEntry {
     pub creditor: [u8; 20],
     pub debtor: [u8; 20],
@@ -238,28 +451,724 @@ impl FromRow<'_, PgRow> for LedgerEntry {
     }
 }
 
-pub async fn get_ledger_by_address<'a, E>(pool: E, address: [u8; 20]) -> Result<Vec<LedgerEntry>>
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LedgerDirection {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LedgerEntryType {
+    Transfer,
+    Upgrade,
+}
+
+impl LedgerEntryType {
+    pub const ALL: [Self; 2] = [Self::Transfer, Self::Upgrade];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Transfer => "transfer",
+            Self::Upgrade => "upgrade",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LedgerQuery {
+    pub address: [u8; 20],
+    // Ledger id to continue after, exclusive
+    pub cursor: Option<i64>,
+    // The whole history when `None`
+    pub limit: Option<i64>,
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    pub direction: Option<LedgerDirection>,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LedgerHistoryEntry {
+    pub id: i64,
+    pub transaction_hash: [u8; 32],
+    pub block_number: Option<i64>,
+    pub timestamp: Option<PrimitiveDateTime>,
+    pub entry_type: LedgerEntryType,
+    pub entry: LedgerEntry,
+}
+
+impl FromRow<'_, PgRow> for LedgerHistoryEntry {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.get("id"),
+            transaction_hash: row
+                .get::<Vec<u8>, _>("transaction_hash")
+                .try_into()
+                .unwrap(),
+            block_number: row.get("block_number"),
+            timestamp: row.get("timestamp"),
+            entry_type: match row.get::<&str, _>("entry_type") {
+                "upgrade" => LedgerEntryType::Upgrade,
+                _ => LedgerEntryType::Transfer,
+            },
+            entry: LedgerEntry::from_row(row)?,
+        })
+    }
+}
+
+// Entries paid out of the legacy account are upgrades. The address is resolved
+// to its account id first so the ledger's account indexes can be used.
+pub async fn get_ledger_history<'a, E>(
+    pool: E,
+    ledger_query: &LedgerQuery,
+) -> Result<Vec<LedgerHistoryEntry>>
 where
     E: Executor<'a, Database = Postgres>,
 {
-    let entries: Vec<LedgerEntry> = query_as(
+    let mut builder: QueryBuilder<'_, Postgres> = QueryBuilder::new(
+        "SELECT ledger.id,
+        ledger.value,
+        transactions.hash AS transaction_hash,
+        transactions.block_number,
+        blocks.timestamp,
+        accounts_debtor.address AS debtor_address,
+        accounts_creditor.address AS creditor_address,
+        CASE
+            WHEN accounts_creditor.address = ",
+    );
+    builder.push_bind(LEGACY_ACCOUNT);
+    builder.push(
+        " THEN 'upgrade'
+            ELSE 'transfer'
+        END AS entry_type
+        FROM ledger
+        JOIN transactions ON ledger.transaction_id = transactions.id
+        JOIN accounts accounts_creditor ON ledger.creditor_id = accounts_creditor.id
+        JOIN accounts accounts_debtor ON ledger.debtor_id = accounts_debtor.id
+        LEFT JOIN blocks ON transactions.block_number = blocks.number
+        WHERE ",
+    );
+    builder.push(match ledger_query.direction {
+        Some(LedgerDirection::In) => "ledger.debtor_id = ",
+        Some(LedgerDirection::Out) => "ledger.creditor_id = ",
+        None => "",
+    });
+    builder.push("(SELECT id FROM accounts WHERE address = ");
+    builder.push_bind(ledger_query.address);
+    builder.push(")");
+    if ledger_query.direction.is_none() {
+        builder.push(" IN (ledger.creditor_id, ledger.debtor_id)");
+    }
+    if let Some(from_block) = ledger_query.from_block {
+        builder.push(" AND transactions.block_number >= ");
+        builder.push_bind(from_block);
+    }
+    if let Some(to_block) = ledger_query.to_block {
+        builder.push(" AND transactions.block_number <= ");
+        builder.push_bind(to_block);
+    }
+    if let Some(cursor) = ledger_query.cursor {
+        builder.push(if ledger_query.descending {
+            " AND ledger.id < "
+        } else {
+            " AND ledger.id > "
+        });
+        builder.push_bind(cursor);
+    }
+    builder.push(if ledger_query.descending {
+        " ORDER BY ledger.id DESC"
+    } else {
+        " ORDER BY ledger.id"
+    });
+    if let Some(limit) = ledger_query.limit {
+        builder.push(" LIMIT ");
+        builder.push_bind(limit);
+    }
+
+    Ok(builder.build_query_as().fetch_all(pool).await?)
+}
+
+pub async fn get_ledger_by_transaction_id<'a, E>(
+    pool: E,
+    transaction_id: i64,
+) -> Result<Vec<LedgerEntry>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(
         "SELECT
         accounts_debtor.address as debtor_address,
         accounts_creditor.address as creditor_address,
         ledger.value
-        from
-         ledger
-        join transactions on ledger.transaction_id = transactions.id
-        join accounts accounts_creditor on ledger.creditor_id = accounts_creditor.id
-        join accounts accounts_debtor on ledger.debtor_id = accounts_debtor.id
+        FROM ledger
+        JOIN accounts accounts_creditor ON ledger.creditor_id = accounts_creditor.id
+        JOIN accounts accounts_debtor ON ledger.debtor_id = accounts_debtor.id
+        WHERE ledger.transaction_id = $1
+        ORDER BY ledger.id",
+    )
+    .bind(transaction_id)
+    .fetch_all(pool)
+    .await?)
+}
 
-       where $1 IN (accounts_creditor.address, accounts_debtor.address);",
+pub async fn get_spent_legacy_outputs_by_transaction_id<'a, E>(
+    pool: E,
+    transaction_id: i64,
+) -> Result<Vec<Outpoint>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (Vec<u8>, i16)>(
+        "SELECT hash, index FROM spent_legacy_outputs WHERE transaction_id = $1 ORDER BY id",
     )
-    .bind(address)
+    .bind(transaction_id)
     .fetch_all(pool)
     .await?
-    .into();
-    Ok(entries)
+    .into_iter()
+    .map(|(hash, index)| Outpoint {
+        hash: hash.try_into().unwrap(),
+        index,
+    })
+    .collect())
+}
+
+pub async fn get_balances<'a, E>(pool: E) -> Result<Vec<([u8; 20], i64)>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(
+        query_as::<_, (Vec<u8>, i64)>("SELECT address, balance FROM accounts ORDER BY id")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|(address, balance)| (address.try_into().unwrap(), balance))
+            .collect(),
+    )
+}
+
+pub async fn get_head_block<'a, E>(pool: E, max_block_number: i64) -> Result<Option<Block>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(
+        "SELECT number, hash, timestamp, signature FROM blocks WHERE number <= $1 ORDER BY number DESC LIMIT 1",
+    )
+    .bind(max_block_number)
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub struct AccountState {
+    pub address: [u8; 20],
+    pub balance: i64,
+    pub nonce: i64,
+}
+
+// Balances are rewound by undoing every ledger entry that is not yet in a block at or
+// below `block_number`
+pub async fn get_account_states_at<'a, E>(pool: E, block_number: i64) -> Result<Vec<AccountState>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (Vec<u8>, i64, i64)>(
+        "SELECT accounts.address,
+        accounts.balance
+        - COALESCE((SELECT SUM(ledger.value) FROM ledger
+            JOIN transactions ON ledger.transaction_id = transactions.id
+            WHERE ledger.debtor_id = accounts.id
+            AND (transactions.block_number IS NULL OR transactions.block_number > $1)), 0)::BIGINT
+        + COALESCE((SELECT SUM(ledger.value) FROM ledger
+            JOIN transactions ON ledger.transaction_id = transactions.id
+            WHERE ledger.creditor_id = accounts.id
+            AND (transactions.block_number IS NULL OR transactions.block_number > $1)), 0)::BIGINT,
+        accounts.imported_nonce + (SELECT COUNT(*) FROM transactions
+            WHERE transactions.account_id = accounts.id AND transactions.block_number <= $1)
+        FROM accounts
+        ORDER BY accounts.id",
+    )
+    .bind(block_number)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(address, balance, nonce)| AccountState {
+        address: address.try_into().unwrap(),
+        balance,
+        nonce,
+    })
+    .collect())
+}
+
+pub struct SpentLegacyOutput {
+    pub hash: [u8; 32],
+    pub index: i16,
+    pub transaction_hash: Option<[u8; 32]>,
+}
+
+pub async fn get_spent_legacy_output<'a, E>(
+    pool: E,
+    hash: [u8; 32],
+    index: i16,
+) -> Result<Option<SpentLegacyOutput>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (Vec<u8>, i16, Option<Vec<u8>>)>(
+        "SELECT hash, index, transaction_hash FROM spent_legacy_outputs WHERE hash = $1 AND index = $2",
+    )
+    .bind(hash)
+    .bind(index)
+    .fetch_optional(pool)
+    .await?
+    .map(|(hash, index, transaction_hash)| SpentLegacyOutput {
+        hash: hash.try_into().unwrap(),
+        index,
+        transaction_hash: transaction_hash.map(|hash| hash.try_into().unwrap()),
+    }))
+}
+
+pub async fn get_spent_legacy_outputs_in<'a, E>(
+    pool: E,
+    outpoints: &[([u8; 32], i16)],
+) -> Result<Vec<SpentLegacyOutput>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let (hashes, indexes): (Vec<Vec<u8>>, Vec<i16>) = outpoints
+        .iter()
+        .map(|(hash, index)| (hash.to_vec(), *index))
+        .unzip();
+
+    Ok(query_as::<_, (Vec<u8>, i16, Option<Vec<u8>>)>(
+        "SELECT hash, index, transaction_hash FROM spent_legacy_outputs
+        WHERE (hash, index) IN (SELECT * FROM UNNEST($1::BYTEA[], $2::SMALLINT[]))",
+    )
+    .bind(hashes)
+    .bind(indexes)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(hash, index, transaction_hash)| SpentLegacyOutput {
+        hash: hash.try_into().unwrap(),
+        index,
+        transaction_hash: transaction_hash.map(|hash| hash.try_into().unwrap()),
+    })
+    .collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpgradeTotals {
+    pub upgraded: i64,
+    pub outpoints: i64,
+    // BTC2 accounts credited by upgrades
+    pub recipients: i64,
+}
+
+// Upgrades are the ledger entries paid out of LEGACY_ACCOUNT
+pub async fn get_upgrade_totals<'a, E>(pool: E) -> Result<UpgradeTotals>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let (upgraded, recipients, outpoints) = query_as::<_, (i64, i64, i64)>(
+        "SELECT COALESCE(SUM(ledger.value), 0)::BIGINT,
+        COUNT(DISTINCT ledger.debtor_id),
+        (SELECT COUNT(*) FROM spent_legacy_outputs)
+        FROM ledger
+        JOIN accounts accounts_creditor ON ledger.creditor_id = accounts_creditor.id
+        WHERE accounts_creditor.address = $1",
+    )
+    .bind(LEGACY_ACCOUNT)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(UpgradeTotals {
+        upgraded,
+        outpoints,
+        recipients,
+    })
+}
+
+// Outpoints and amounts per script type, outputs without recorded details are left out
+pub async fn get_upgrade_totals_by_script_type<'a, E>(pool: E) -> Result<Vec<(String, i64, i64)>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(
+        "SELECT script_type, COUNT(*), SUM(amount)::BIGINT FROM spent_legacy_outputs
+        WHERE script_type IS NOT NULL
+        GROUP BY script_type
+        ORDER BY script_type",
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+// Outputs spent before their script type and amount were recorded, or imported from a snapshot
+pub async fn get_spent_legacy_outputs_without_details<'a, E>(
+    pool: E,
+) -> Result<Vec<(i64, [u8; 32], i16)>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (i64, Vec<u8>, i16)>(
+        "SELECT id, hash, index FROM spent_legacy_outputs WHERE script_type IS NULL ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(id, hash, index)| (id, hash.try_into().unwrap(), index))
+    .collect())
+}
+
+pub async fn set_spent_legacy_output_details<'a, E>(
+    e: E,
+    id: i64,
+    output: &LegacyOutput,
+) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query("UPDATE spent_legacy_outputs SET script_type = $2, amount = $3 WHERE id = $1")
+        .bind(id)
+        .bind(output.script_type.name())
+        .bind(output.amount)
+        .execute(e)
+        .await?;
+    Ok(())
+}
+
+// Per block totals, pending upgrades are left out until they are in a block
+pub async fn get_upgrade_series<'a, E>(pool: E) -> Result<Vec<(i64, UpgradeTotals)>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (i64, i64, i64, i64)>(
+        "SELECT transactions.block_number,
+        SUM(ledger.value)::BIGINT,
+        COUNT(DISTINCT ledger.debtor_id),
+        (
+            SELECT COUNT(*) FROM spent_legacy_outputs
+            JOIN transactions spending ON spent_legacy_outputs.transaction_id = spending.id
+            WHERE spending.block_number = transactions.block_number
+        )
+        FROM ledger
+        JOIN transactions ON ledger.transaction_id = transactions.id
+        JOIN accounts accounts_creditor ON ledger.creditor_id = accounts_creditor.id
+        WHERE accounts_creditor.address = $1 AND transactions.block_number IS NOT NULL
+        GROUP BY transactions.block_number
+        ORDER BY transactions.block_number",
+    )
+    .bind(LEGACY_ACCOUNT)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(block_number, upgraded, recipients, outpoints)| {
+        (
+            block_number,
+            UpgradeTotals {
+                upgraded,
+                outpoints,
+                recipients,
+            },
+        )
+    })
+    .collect())
+}
+
+pub async fn get_spent_legacy_outputs_at<'a, E>(
+    pool: E,
+    block_number: i64,
+) -> Result<Vec<SpentLegacyOutput>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (Vec<u8>, i16, Option<Vec<u8>>)>(
+        "SELECT spent_legacy_outputs.hash, spent_legacy_outputs.index, spent_legacy_outputs.transaction_hash
+        FROM spent_legacy_outputs
+        LEFT JOIN transactions ON spent_legacy_outputs.transaction_id = transactions.id
+        WHERE spent_legacy_outputs.transaction_id IS NULL OR transactions.block_number <= $1
+        ORDER BY spent_legacy_outputs.id",
+    )
+    .bind(block_number)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(hash, index, transaction_hash)| SpentLegacyOutput {
+        hash: hash.try_into().unwrap(),
+        index,
+        transaction_hash: transaction_hash.map(|hash| hash.try_into().unwrap()),
+    })
+    .collect())
+}
+
+// Reverts a transaction that is not in a block yet. Only the signer's latest
+// transaction can be dropped so nonces stay contiguous.
+pub async fn drop_pending_transaction(pool: &sqlx::Pool<Postgres>, hash: [u8; 32]) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let Some((transaction_id, account_id)) = query_as::<_, (i64, i64)>(
+        "SELECT id, account_id FROM transactions
+        WHERE hash = $1 AND block_number IS NULL
+        FOR UPDATE",
+    )
+    .bind(hash)
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Ok(false);
+    };
+    let has_later_transactions: bool =
+        query("SELECT EXISTS (SELECT 1 FROM transactions WHERE account_id = $1 AND id > $2)")
+            .bind(account_id)
+            .bind(transaction_id)
+            .fetch_one(&mut *tx)
+            .await?
+            .get(0);
+    if has_later_transactions {
+        return Err(Error::Error(
+            "The signer has later transactions".to_string(),
+        ));
+    }
+
+    let balances: Vec<i64> = query_as::<_, (i64,)>(
+        "UPDATE accounts SET balance = accounts.balance + deltas.delta
+        FROM (
+            SELECT account_id, SUM(delta)::BIGINT AS delta FROM (
+                SELECT creditor_id AS account_id, value AS delta FROM ledger WHERE transaction_id = $1
+                UNION ALL
+                SELECT debtor_id AS account_id, -value AS delta FROM ledger WHERE transaction_id = $1
+            ) entries
+            GROUP BY account_id
+        ) deltas
+        WHERE accounts.id = deltas.account_id
+        RETURNING accounts.balance",
+    )
+    .bind(transaction_id)
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|(balance,)| balance)
+    .collect();
+    // A recipient already spent what it was sent
+    if balances.iter().any(|balance| *balance < 0) {
+        return Err(Error::Error(
+            "The transaction's outputs have been spent".to_string(),
+        ));
+    }
+
+    for statement in [
+        "DELETE FROM ledger WHERE transaction_id = $1",
+        "DELETE FROM spent_legacy_outputs WHERE transaction_id = $1",
+        "DELETE FROM transactions WHERE id = $1",
+    ] {
+        query(statement)
+            .bind(transaction_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+pub async fn import_account_state<'a, E>(e: E, account: &AccountState) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query(
+        "INSERT INTO accounts (address, balance, imported_balance, imported_nonce) VALUES ($1, $2, $2, $3)
+        ON CONFLICT (address) DO UPDATE SET balance = EXCLUDED.balance, imported_balance = EXCLUDED.imported_balance, imported_nonce = EXCLUDED.imported_nonce",
+    )
+    .bind(account.address)
+    .bind(account.balance)
+    .bind(account.nonce)
+    .execute(e)
+    .await?;
+    Ok(())
+}
+
+pub async fn insert_imported_snapshot<'a, E>(e: E, height: i64) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query("INSERT INTO imported_snapshots (height) VALUES ($1)")
+        .bind(height)
+        .execute(e)
+        .await?;
+    Ok(())
+}
+
+// `None` unless the database was bootstrapped from a snapshot
+pub async fn get_imported_height<'a, E>(pool: E) -> Result<Option<i64>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(
+        query_as::<_, (Option<i64>,)>("SELECT MAX(height) FROM imported_snapshots")
+            .fetch_one(pool)
+            .await?
+            .0,
+    )
+}
+
+// Balances as of the imported snapshot, before any of the stored transactions
+pub async fn get_imported_balances<'a, E>(pool: E) -> Result<Vec<([u8; 20], i64)>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as::<_, (Vec<u8>, i64)>(
+        "SELECT address, imported_balance FROM accounts WHERE imported_balance != 0 ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|(address, balance)| (address.try_into().unwrap(), balance))
+    .collect())
+}
+
+pub async fn import_spent_legacy_output<'a, E>(e: E, output: &SpentLegacyOutput) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query("INSERT INTO spent_legacy_outputs (transaction_hash, hash, index) VALUES ($1, $2, $3)")
+        .bind(output.transaction_hash)
+        .bind(output.hash)
+        .bind(output.index)
+        .execute(e)
+        .await?;
+    Ok(())
+}
+
+// Inserts a block with a known number and moves `blocks_number_seq` past it so the block
+// producer continues from there
+pub async fn import_block<'a, E>(e: E, block: &Block) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query(
+        "WITH block AS (INSERT INTO blocks (number, hash, timestamp, signature) VALUES ($1, $2, $3, $4) RETURNING number)
+        SELECT setval('blocks_number_seq', (SELECT number FROM block))",
+    )
+    .bind(block.number)
+    .bind(block.hash)
+    .bind(block.timestamp)
+    .bind(block.signature)
+    .execute(e)
+    .await?;
+    Ok(())
+}
+
+pub struct Anchor {
+    pub block_number: i64,
+    pub block_hash: [u8; 32],
+    pub txid: [u8; 32],
+    pub psbt: Vec<u8>,
+    pub funding_outpoints: Vec<String>,
+    pub bitcoin_block_hash: Option<[u8; 32]>,
+    pub bitcoin_block_height: Option<i64>,
+}
+
+impl FromRow<'_, PgRow> for Anchor {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            block_number: row.get("block_number"),
+            block_hash: row.get::<Vec<u8>, _>("block_hash").try_into().unwrap(),
+            txid: row.get::<Vec<u8>, _>("txid").try_into().unwrap(),
+            psbt: row.get("psbt"),
+            funding_outpoints: row.get("funding_outpoints"),
+            bitcoin_block_hash: row
+                .get::<Option<Vec<u8>>, _>("bitcoin_block_hash")
+                .map(|hash| hash.try_into().unwrap()),
+            bitcoin_block_height: row.get("bitcoin_block_height"),
+        })
+    }
+}
+
+pub async fn insert_anchor<'a, E>(e: E, anchor: &Anchor) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query("INSERT INTO anchors (block_number, block_hash, txid, psbt, funding_outpoints) VALUES ($1, $2, $3, $4, $5)")
+        .bind(anchor.block_number)
+        .bind(anchor.block_hash)
+        .bind(anchor.txid)
+        .bind(&anchor.psbt)
+        .bind(&anchor.funding_outpoints)
+        .execute(e)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_anchors<'a, E>(pool: E, pending_only: bool) -> Result<Vec<Anchor>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(
+        "SELECT * FROM anchors WHERE NOT $1 OR bitcoin_block_hash IS NULL ORDER BY block_number",
+    )
+    .bind(pending_only)
+    .fetch_all(pool)
+    .await?)
+}
+
+// Funding outpoints of each pending anchor and whether it is older than `timeout` seconds
+pub async fn get_anchor_reservations<'a, E>(
+    pool: E,
+    timeout: i64,
+) -> Result<Vec<(Vec<String>, bool)>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(query_as(
+        "SELECT funding_outpoints, created_at < NOW() - make_interval(secs => $1) FROM anchors
+        WHERE bitcoin_block_hash IS NULL
+        ORDER BY block_number",
+    )
+    .bind(timeout)
+    .fetch_all(pool)
+    .await?)
+}
+
+// When a fee bump replaces the anchor that was built
+pub async fn replace_anchor_txid<'a, E>(e: E, txid: [u8; 32], replacement: [u8; 32]) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query("UPDATE anchors SET txid = $2 WHERE txid = $1")
+        .bind(txid)
+        .bind(replacement)
+        .execute(e)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_last_anchored_block_number<'a, E>(pool: E) -> Result<Option<i64>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    Ok(
+        query_as::<_, (Option<i64>,)>("SELECT MAX(block_number) FROM anchors")
+            .fetch_one(pool)
+            .await?
+            .0,
+    )
+}
+
+pub async fn confirm_anchor<'a, E>(
+    e: E,
+    txid: [u8; 32],
+    bitcoin_block_hash: [u8; 32],
+    bitcoin_block_height: i64,
+) -> Result<()>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    query("UPDATE anchors SET bitcoin_block_hash = $2, bitcoin_block_height = $3, confirmed_at = NOW() WHERE txid = $1")
+        .bind(txid)
+        .bind(bitcoin_block_hash)
+        .bind(bitcoin_block_height)
+        .execute(e)
+        .await?;
+    Ok(())
 }
 
 pub async fn deposit<E>(pool: E, account: [u8; 20], starting_balance: i64) -> Result<()>
@@ -277,13 +1186,27 @@ where
 pub async fn insert_block<'a, E: Executor<'a, Database = Postgres>>(
     e: E,
     hash: [u8; 32],
-) -> Result<i64> {
+    signature: Option<[u8; 65]>,
+) -> Result<Block> {
+    Ok(query_as(
+        "INSERT INTO blocks (hash, signature, timestamp) VALUES ($1, $2, NOW()) RETURNING number, hash, timestamp, signature",
+    )
+    .bind(hash)
+    .bind(signature)
+    .fetch_one(e)
+    .await?)
+}
+
+pub async fn get_transaction_id_by_hash<'a, E>(pool: E, hash: [u8; 32]) -> Result<Option<i64>>
+where
+    E: Executor<'a, Database = Postgres>,
+{
     Ok(
-        query("INSERT INTO blocks (hash, timestamp) VALUES ($1, NOW()) RETURNING number")
+        query_as::<_, (i64,)>("SELECT id FROM transactions WHERE hash = $1")
             .bind(hash)
-            .fetch_one(e)
-            .await
-            .map(|row| row.get(0))?,
+            .fetch_optional(pool)
+            .await?
+            .map(|(id,)| id),
     )
 }
 
@@ -294,7 +1217,7 @@ pub async fn insert_transaction<'a, E: Executor<'a, Database = Postgres>>(
 ) -> Result<i64> {
     let mut signature = Vec::new();
     signed_transaction.signature().encode(&mut signature);
-    let record = query("INSERT INTO transactions (hash, account_id, nonce, gas_price, _to, value, input, signature) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id")
+    let record = query("INSERT INTO transactions (hash, account_id, nonce, gas_price, _to, value, input, signature, raw) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id")
 
         .bind(signed_transaction.hash().to_vec())
         .bind(account_id)
@@ -304,6 +1227,7 @@ pub async fn insert_transaction<'a, E: Executor<'a, Database = Postgres>>(
         .bind(scale_down(signed_transaction.transaction.value()))
         .bind(signed_transaction.transaction.input().to_vec())
         .bind(signature)
+        .bind(signed_transaction.envelope_encoded().to_vec())
         .fetch_one(e)
         .await;
 
@@ -343,17 +1267,32 @@ pub async fn get_or_insert_account_id<'a, E: Executor<'a, Database = Postgres>>(
     Ok(result.get(0))
 }
 
+// The nonce carried over from an imported snapshot plus the transactions sent since.
+// Transactions are matched on `account_id`, joining on `accounts.id` counted another
+// account's transactions.
 pub async fn get_transaction_count_by_address<E>(pool: E, address: [u8; 20]) -> Result<i64>
 where
     E: Executor<'static, Database = Postgres>,
 {
-    Ok(query("SELECT COUNT(*) FROM transactions JOIN accounts on transactions.id = accounts.id WHERE accounts.address = $1")
+    Ok(query("SELECT COALESCE((SELECT imported_nonce FROM accounts WHERE address = $1), 0) + (SELECT COUNT(*) FROM transactions JOIN accounts on transactions.account_id = accounts.id WHERE accounts.address = $1)")
             .bind(address)
             .fetch_one(pool)
             .await?.get(0)
         )
 }
 
+// Transactions waiting for a block
+pub async fn get_pending_transaction_count<'a, E: Executor<'a, Database = Postgres>>(
+    pool: E,
+) -> Result<i64> {
+    Ok(
+        query("SELECT COUNT(*) FROM transactions WHERE block_number IS NULL")
+            .fetch_one(pool)
+            .await?
+            .get(0),
+    )
+}
+
 pub async fn get_transaction_count<'a, E: Executor<'a, Database = Postgres>>(
     pool: E,
 ) -> Result<i64> {
@@ -398,4 +1337,132 @@ mod tests {
 
         Ok(())
     }
+
+    const SENDER: [u8; 20] = hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625");
+    const RECIPIENT: [u8; 20] = hex_lit::hex!("3073ac44aA1b95f2fe71Bb2eb36b9CE27892F8ee");
+
+    fn signed(raw: &str) -> TransactionSigned {
+        TransactionSigned::decode_rlp_legacy_transaction(&mut &hex::decode(raw).unwrap()[..])
+            .unwrap()
+    }
+
+    // Nonces 0 and 1 from SENDER
+    fn sender_transactions() -> [TransactionSigned; 2] {
+        [
+            signed("f8698080825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b2000080820188a0db848c751522df8fb1d9c317f344b40251bc73c6db7a4d8dfadf929f1c21e21aa01a4203287ae5b0a3f1c98e79e08b49cc5dafd6d96e5845b6d403250e1461a851"),
+            signed("f8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb"),
+        ]
+    }
+
+    // An upgrade message, signed by another key than SENDER
+    fn other_transaction() -> TransactionSigned {
+        signed("f90227068082520894000000000000000000000000000000000000000080b901c4e60b060d0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000014000000000000000000000000000000000000000000000000000000000000000cd416374696f6e3a20557067726164650a44657374696e6174696f6e20436861696e2049443a203230330a44657374696e6174696f6e20416464726573733a203078663230344545353539364341626336456336306535653932466434313245413766383536623632350a496e707574733a0a20202d0a20202020486173683a20343931363865626338323661383263633834633031333936363064396261666239313961366135316432663031626633313632393839363036316533393464300a20202020496e6465783a203000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004120239ff874c5e9bcdccf84c0e68333d0337f43847d54d56a3b1e339b4b59a975fb59d0741f8857b9467d00ca418d40f3a7f02e4fa4cbd68daf5c29163310724fdf00000000000000000000000000000000000000000000000000000000000000820188a0af7479b422eefc7e6f3051922e63ceb5330fdd08da66c22d5619f2ba514e24faa01ff98a79df5b2b48007e99e810e0d1d6aeed931993401880efef5dae7f46d790")
+    }
+
+    async fn transfer(
+        pool: &PgPool,
+        transaction_signed: &TransactionSigned,
+        from: [u8; 20],
+        to: [u8; 20],
+        value: i64,
+    ) {
+        let mut transaction = Transaction::new(pool, transaction_signed).await.unwrap();
+        transaction.transfer(from, to, value).await.unwrap();
+        transaction.commit().await.unwrap();
+    }
+
+    #[sqlx::test]
+    async fn drop_pending_transaction(pool: PgPool) -> sqlx::Result<()> {
+        deposit(&pool, SENDER, 100000000).await.unwrap();
+        let [first, second] = sender_transactions();
+        transfer(&pool, &first, SENDER, RECIPIENT, 30000000).await;
+        transfer(&pool, &second, SENDER, RECIPIENT, 20000000).await;
+
+        // Dropping the first would leave a gap before the second's nonce
+        assert!(matches!(
+            super::drop_pending_transaction(&pool, first.hash().0).await,
+            Err(Error::Error(_))
+        ));
+
+        assert!(super::drop_pending_transaction(&pool, second.hash().0)
+            .await
+            .unwrap());
+        assert_eq!(get_balance(&pool, SENDER).await.unwrap(), 70000000);
+        assert_eq!(get_balance(&pool, RECIPIENT).await.unwrap(), 30000000);
+        assert!(get_transaction_id_by_hash(&pool, second.hash().0)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(!super::drop_pending_transaction(&pool, second.hash().0)
+            .await
+            .unwrap());
+
+        assert!(super::drop_pending_transaction(&pool, first.hash().0)
+            .await
+            .unwrap());
+        assert_eq!(get_balance(&pool, SENDER).await.unwrap(), 100000000);
+        assert_eq!(get_balance(&pool, RECIPIENT).await.unwrap(), 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn drop_spent_transaction(pool: PgPool) -> sqlx::Result<()> {
+        deposit(&pool, SENDER, 100000000).await.unwrap();
+        let [first, _] = sender_transactions();
+        transfer(&pool, &first, SENDER, RECIPIENT, 30000000).await;
+        // Signed by someone else, so `first` is still the sender's latest
+        transfer(&pool, &other_transaction(), RECIPIENT, SENDER, 30000000).await;
+
+        assert!(matches!(
+            super::drop_pending_transaction(&pool, first.hash().0).await,
+            Err(Error::Error(_))
+        ));
+        assert!(get_transaction_id_by_hash(&pool, first.hash().0)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(get_balance(&pool, RECIPIENT).await.unwrap(), 0);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn drop_upgrade(pool: PgPool) -> sqlx::Result<()> {
+        let upgrade = other_transaction();
+        let mut transaction = Transaction::new(&pool, &upgrade).await.unwrap();
+        transaction
+            .upgrade(
+                vec![(
+                    Outpoint {
+                        hash: [1; 32],
+                        index: 0,
+                    },
+                    LegacyOutput {
+                        amount: 136265,
+                        script_type: crate::bitcoin_legacy::utxos::ScriptType::P2wpkh,
+                    },
+                )],
+                SENDER,
+            )
+            .await
+            .unwrap();
+        transaction.commit().await.unwrap();
+        assert!(get_spent_legacy_output(&pool, [1; 32], 0)
+            .await
+            .unwrap()
+            .is_some());
+
+        assert!(super::drop_pending_transaction(&pool, upgrade.hash().0)
+            .await
+            .unwrap());
+        // The outpoint can be upgraded again
+        assert!(get_spent_legacy_output(&pool, [1; 32], 0)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(get_balance(&pool, SENDER).await.unwrap(), 0);
+
+        Ok(())
+    }
 }
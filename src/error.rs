@@ -1,6 +1,11 @@
-use axum::response::{IntoResponse, Response};
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::ser::StdError;
-use std::{convert::Infallible, num::TryFromIntError};
+use serde_json::{json, Value};
+use std::{convert::Infallible, num::TryFromIntError, time::Duration};
 
 #[derive(thiserror::Error, Debug, Clone)]
 pub enum Error {
@@ -16,8 +21,12 @@ pub enum Error {
     ParseError(String),
     #[error("{0}")]
     SqlxError(String),
-    #[error("{0}")]
+    #[error("Unsupported method {0}")]
     UnsupportedMethod(String),
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("{0}")]
+    InvalidJson(String),
     #[error("Invalid transaction")]
     InvalidTransaction,
     #[error("Invalid Signature")]
@@ -26,6 +35,15 @@ pub enum Error {
     FunctionNotFound,
     #[error("Bad Request")]
     BadRequest,
+    #[error("Rate limit exceeded, retry in {} seconds", .0.as_secs())]
+    RateLimited(Duration),
+    #[error("Transaction intake is paused")]
+    IntakePaused,
+    #[error("Unauthorized")]
+    Unauthorized,
+    // The transaction was valid but applying it failed, like a reverted EVM call
+    #[error("{0}")]
+    ExecutionError(String),
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -34,14 +52,110 @@ pub fn _err(description: &str) -> Error {
     Error::Error(description.to_string())
 }
 
+// https://www.jsonrpc.org/specification#error_object
+impl Error {
+    pub fn code(&self) -> i64 {
+        match self {
+            Error::InvalidJson(_) => -32700,
+            Error::InvalidRequest(_) => -32600,
+            Error::UnsupportedMethod(_) => -32601,
+            Error::ParseError(_) => -32602,
+            Error::IoError(_) | Error::SqlxError(_) => -32603,
+            // https://eips.ethereum.org/EIPS/eip-1474#error-codes
+            Error::RateLimited(_) => -32005,
+            // What Ethereum clients return for reverted calls, with the reason in `data`
+            Error::ExecutionError(_) | Error::FunctionNotFound => 3,
+            _ => -32000,
+        }
+    }
+
+    pub fn data(&self) -> Option<Value> {
+        match self {
+            Error::ExecutionError(reason) => Some(revert_data(reason)),
+            Error::FunctionNotFound => Some(revert_data(&self.to_string())),
+            Error::RateLimited(retry_after) => Some(json!({ "retryAfter": retry_after.as_secs() })),
+            _ => None,
+        }
+    }
+
+    // Used as a metrics label
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::OutpointNotFound => "OutpointNotFound",
+            Error::InvalidScript => "InvalidScript",
+            Error::Error(_) => "Error",
+            Error::IoError(_) => "IoError",
+            Error::ParseError(_) => "ParseError",
+            Error::SqlxError(_) => "SqlxError",
+            Error::UnsupportedMethod(_) => "UnsupportedMethod",
+            Error::InvalidRequest(_) => "InvalidRequest",
+            Error::InvalidJson(_) => "InvalidJson",
+            Error::InvalidTransaction => "InvalidTransaction",
+            Error::InvalidSignature => "InvalidSignature",
+            Error::FunctionNotFound => "FunctionNotFound",
+            Error::BadRequest => "BadRequest",
+            Error::RateLimited(_) => "RateLimited",
+            Error::IntakePaused => "IntakePaused",
+            Error::Unauthorized => "Unauthorized",
+            Error::ExecutionError(_) => "ExecutionError",
+        }
+    }
+
+    pub fn to_json_rpc(&self, id: Value) -> Value {
+        let mut error = json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        });
+        if let Some(data) = self.data() {
+            error["data"] = data;
+        }
+
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": error,
+        })
+    }
+}
+
+// ABI encoded `Error(string)`, the same revert data a Solidity `revert(reason)` produces
+fn revert_data(reason: &str) -> Value {
+    let padding = (32 - reason.len() % 32) % 32;
+    let data = [
+        &[0x08, 0xc3, 0x79, 0xa0][..],
+        &[[0; 24], 32u64.to_be_bytes()].concat(),
+        &[[0; 24], (reason.len() as u64).to_be_bytes()].concat(),
+        reason.as_bytes(),
+        &vec![0; padding],
+    ]
+    .concat();
+
+    json!(format!("0x{}", hex::encode(data)))
+}
+
+// Only reached when the request id is unknown
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        (format!("{}", self.to_string())).into_response()
+        match self {
+            Error::RateLimited(retry_after) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.as_secs().to_string())],
+                Json(self.to_json_rpc(Value::Null)),
+            )
+                .into_response(),
+            _ => Json(self.to_json_rpc(Value::Null)).into_response(),
+        }
     }
 }
 
 impl From<sqlx::Error> for Error {
     fn from(err: sqlx::Error) -> Self {
+        // Raised by the ledger's `validate_entry` trigger, a rejection rather than a revert
+        if let Some(database_error) = err.as_database_error() {
+            if database_error.message().starts_with("Insufficient funds") {
+                return Error::Error("Insufficient funds".to_string());
+            }
+        }
         Error::SqlxError(err.to_string())
     }
 }
@@ -60,7 +174,7 @@ impl From<Vec<u8>> for Error {
 
 impl From<hex::FromHexError> for Error {
     fn from(err: hex::FromHexError) -> Self {
-        Error::Error(err.to_string())
+        Error::ParseError(err.to_string())
     }
 }
 
@@ -71,10 +185,28 @@ impl From<Infallible> for Error {
 }
 impl From<TryFromIntError> for Error {
     fn from(err: TryFromIntError) -> Self {
+        Error::ParseError(err.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(err: tokio::task::JoinError) -> Self {
         Error::Error(err.to_string())
     }
 }
 
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::IoError(err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::IoError(err.to_string())
+    }
+}
+
 impl From<alloy_rlp::Error> for Error {
     fn from(err: alloy_rlp::Error) -> Self {
         Error::Error(err.to_string())
@@ -0,0 +1,24 @@
+use crate::db::Block;
+use lazy_static::lazy_static;
+use tokio::sync::broadcast;
+
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    NewHead { block: Block, parent_hash: [u8; 32] },
+    PendingTransaction([u8; 32]),
+}
+
+lazy_static! {
+    static ref EVENTS: broadcast::Sender<Event> = broadcast::channel(CAPACITY).0;
+}
+
+// Nothing is buffered while there are no subscribers
+pub fn publish(event: Event) {
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    EVENTS.subscribe()
+}
@@ -2,7 +2,8 @@ pub mod postgres;
 pub mod upgrade_by_message;
 
 use crate::{
-    constants::{SYSTEM_ADDRESS, UPGRADE_BY_MESSAGE},
+    bitcoin_legacy::utxos::LegacyOutput,
+    constants::{DEFAULT_GAS_LIMIT, SYSTEM_ADDRESS, UPGRADE_BY_MESSAGE},
     db::{
         deposit, get_balance, get_transaction_count, get_transaction_count_by_address, Transaction,
     },
@@ -59,6 +60,7 @@ impl Evm {
         deposit(&db.pool, address, value).await.unwrap()
     }
 
+    #[tracing::instrument(skip_all, fields(hash = %signed_transaction.hash()))]
     pub async fn run_transaction(&self, signed_transaction: &TransactionSigned) -> Result<i64> {
         let db = self.db.lock().await;
 
@@ -95,27 +97,20 @@ impl Evm {
     ) -> Result<()> {
         match signed_transaction.transaction.input().get(0..4) {
             Some(selector) if selector == UPGRADE_BY_MESSAGE => {
-                let (upgrade_by_message, signature, verifying_key) =
-                    UpgradeByMessage::decode(&signed_transaction.transaction.input()[4..]).await?;
-                let signer = signed_transaction
-                .recover_signer()
-                .ok_or(Error::InvalidSignature)?
-                .to_vec()
-                .try_into()?;
-                let amount = upgrade_by_message
-                    .validate(
-                        &[signature.to_vec(), verifying_key.to_sec1_bytes().to_vec()].concat(),
-                        signer
-                    )
-                    .await?;
+                let (upgrade_by_message, signer, outputs) =
+                    validate_upgrade_by_message(signed_transaction).await?;
+                let amount: i64 = outputs.iter().map(|output| output.amount).sum();
                 let _ = transaction
                     .upgrade(
-                        upgrade_by_message.inputs,
+                        upgrade_by_message.inputs.into_iter().zip(outputs).collect(),
                         signer,
-                        amount,
                     )
                     .await?;
-                println!("{} unlocked {} BTC2", hex::encode(signer), amount);
+                tracing::info!(
+                    signer = hex::encode(signer),
+                    amount,
+                    "upgraded legacy outputs"
+                );
                 Ok::<(), Error>(())
             }
             _ => return Err(Error::FunctionNotFound),
@@ -123,6 +118,37 @@ impl Evm {
     }
 }
 
+// Decodes an `upgradeByMessage` call and checks it against the legacy UTXO snapshot,
+// returning the message, the signer and the outputs it unlocks in input order
+pub async fn validate_upgrade_by_message(
+    signed_transaction: &TransactionSigned,
+) -> Result<(UpgradeByMessage, [u8; 20], Vec<LegacyOutput>)> {
+    let (upgrade_by_message, signature, verifying_key) =
+        UpgradeByMessage::decode(&signed_transaction.transaction.input()[4..]).await?;
+    let signer = signed_transaction
+        .recover_signer()
+        .ok_or(Error::InvalidSignature)?
+        .to_vec()
+        .try_into()?;
+    let outputs = upgrade_by_message
+        .validate(
+            &[signature.to_vec(), verifying_key.to_sec1_bytes().to_vec()].concat(),
+            signer,
+        )
+        .await?;
+    Ok((upgrade_by_message, signer, outputs))
+}
+
+// What a transaction would use on Ethereum: 21000 plus 4 gas per zero and 16 per
+// non-zero calldata byte. Gas is never charged, this is only reported in receipts.
+pub fn intrinsic_gas(input: &[u8]) -> u64 {
+    DEFAULT_GAS_LIMIT as u64
+        + input
+            .iter()
+            .map(|byte| if *byte == 0 { 4 } else { 16 })
+            .sum::<u64>()
+}
+
 // 10 ^ 18 (ETH) / 10 ^ 8 (BTC) = 10 ^ 10
 pub const SCALING_FACTOR: i64 = i64::pow(10, 10);
 
@@ -137,3 +163,12 @@ pub fn scale_down(n: U256) -> i64 {
 pub fn scale_up(n: i64) -> U256 {
     U256::from(n) * U256::from(SCALING_FACTOR)
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn intrinsic_gas() {
+        assert_eq!(super::intrinsic_gas(&[]), 21000);
+        assert_eq!(super::intrinsic_gas(&[0, 1, 0, 2]), 21000 + 4 + 16 + 4 + 16);
+    }
+}
@@ -1,6 +1,12 @@
-use crate::{bitcoin_legacy, bitcoin_legacy::utxos, error::Result};
-use ethers_core::abi::ParamType;
+use crate::{
+    bitcoin_legacy,
+    bitcoin_legacy::utxos::{self, LegacyOutput},
+    constants::{CHAIN_ID, UPGRADE_BY_MESSAGE},
+    error::Result,
+};
+use ethers_core::abi::{ParamType, Token};
 use k256::ecdsa::VerifyingKey;
+use reth_primitives::Address;
 use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -53,6 +59,45 @@ where
 }
 
 impl UpgradeByMessage {
+    pub fn new(destination_address: [u8; 20], inputs: Vec<Outpoint>) -> Self {
+        Self {
+            action: "Upgrade".to_string(),
+            destination_chain_id: CHAIN_ID,
+            destination_address,
+            inputs,
+        }
+    }
+
+    // The exact text wallets sign, parses back into the same message
+    pub fn to_message(&self) -> String {
+        let mut message = format!(
+            "Action: {}\nDestination Chain ID: {}\nDestination Address: {}\nInputs:",
+            self.action,
+            self.destination_chain_id,
+            Address::from(self.destination_address).to_checksum(None)
+        );
+        for input in &self.inputs {
+            message.push_str(&format!(
+                "\n  -\n    Hash: {}\n    Index: {}",
+                hex::encode(input.hash),
+                input.index
+            ));
+        }
+        message
+    }
+
+    // `upgradeByMessage(string, bytes)` calldata for a signed message
+    pub fn calldata(&self, signature: &[u8]) -> Vec<u8> {
+        [
+            &UPGRADE_BY_MESSAGE[..],
+            &ethers_core::abi::encode(&[
+                Token::String(self.to_message()),
+                Token::Bytes(signature.to_vec()),
+            ]),
+        ]
+        .concat()
+    }
+
     pub async fn decode(arguments: &[u8]) -> Result<(Self, [u8; 65], VerifyingKey)> {
         if let [message, signature] =
             ethers_core::abi::decode(&[ParamType::String, ParamType::Bytes], &arguments)
@@ -80,11 +125,12 @@ impl UpgradeByMessage {
             Err(crate::error::Error::Error("parse error".into()))
         }
     }
+    #[tracing::instrument(skip_all, fields(inputs = self.inputs.len()))]
     pub async fn validate(
         &self,
         unlocking_script: &[u8],
         destination_address: [u8; 20],
-    ) -> Result<i64> {
+    ) -> Result<Vec<LegacyOutput>> {
         if self.destination_address != destination_address {
              
             return Err(crate::error::Error::Error(
@@ -110,7 +156,38 @@ impl UpgradeByMessage {
                     unlocking_script,
                 )
             })
-            .sum::<Result<i64>>()
+            .collect::<Result<Vec<LegacyOutput>>>()
             .map_err(|e| crate::error::Error::Error(e.to_string()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "Action: Upgrade\nDestination Chain ID: 203\nDestination Address: 0xf204EE5596CAbc6Ec60e5e92Fd412EA7f856b625\nInputs:\n  -\n    Hash: 49168ebc826a82cc84c0139660d9bafb919a6a51d2f01bf31629896061e394d0\n    Index: 0";
+
+    #[test]
+    fn to_message() {
+        let upgrade_by_message: UpgradeByMessage = serde_yaml::from_str(MESSAGE).unwrap();
+        assert_eq!(upgrade_by_message.to_message(), MESSAGE);
+
+        let upgrade_by_message = UpgradeByMessage::new(
+            [1; 20],
+            vec![
+                Outpoint {
+                    hash: [2; 32],
+                    index: 0,
+                },
+                Outpoint {
+                    hash: [3; 32],
+                    index: 7,
+                },
+            ],
+        );
+        assert_eq!(
+            serde_yaml::from_str::<UpgradeByMessage>(&upgrade_by_message.to_message()).unwrap(),
+            upgrade_by_message
+        );
+    }
+}
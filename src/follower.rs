@@ -0,0 +1,336 @@
+use crate::{
+    block_producer::{block_hash, BLOCK_TIME},
+    chain_head::{ChainHead, SyncStatus},
+    constants::SEQUENCER_PUBLIC_KEY,
+    db::{
+        get_head_block, get_transaction_id_by_hash, import_block, update_transactions_block_number,
+        Block,
+    },
+    error::{Error, Result},
+    events::{self, Event},
+    evm::{Evm, TransactionSigned},
+    health,
+    rpc::{client, parse_bytes_like, parse_i64},
+    sequencer,
+};
+use k256::ecdsa::VerifyingKey;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{
+    types::time::{OffsetDateTime, PrimitiveDateTime},
+    PgPool,
+};
+use tokio::time;
+
+const BLOCKS_PER_REQUEST: i64 = 100;
+
+#[derive(Deserialize)]
+struct RawBlock {
+    number: String,
+    hash: String,
+    timestamp: String,
+    signature: Option<String>,
+    transactions: Vec<String>,
+}
+
+// Pulls blocks from the primary in place of `block_producer::start`
+pub async fn start(pool: PgPool, chain_head: ChainHead, primary_url: String) -> Result<()> {
+    let sequencer_public_key = SEQUENCER_PUBLIC_KEY
+        .as_ref()
+        .ok_or(Error::Error("SEQUENCER_PUBLIC_KEY is not set".to_string()))?;
+    let evm = Evm::new(pool.clone());
+    let mut ticker = time::interval(BLOCK_TIME);
+
+    loop {
+        ticker.tick().await;
+        // The primary being unreachable or a bad block is retried on the next tick
+        match sync(&pool, &chain_head, &evm, sequencer_public_key, &primary_url).await {
+            Ok(()) => health::record_slot(),
+            Err(err) => tracing::error!(error = %err, "sync failed"),
+        }
+    }
+}
+
+async fn sync(
+    pool: &PgPool,
+    chain_head: &ChainHead,
+    evm: &Evm,
+    sequencer_public_key: &VerifyingKey,
+    primary_url: &str,
+) -> Result<()> {
+    let highest_block = parse_i64(
+        client::call(primary_url, "eth_blockNumber", json!([]))
+            .await?
+            .as_str()
+            .ok_or(Error::ParseError("Expected block number".to_string()))?,
+    )?;
+    if highest_block > chain_head.block_number() {
+        chain_head.set_sync_status(Some(SyncStatus {
+            starting_block: chain_head.block_number(),
+            highest_block,
+        }));
+    }
+
+    loop {
+        let head = chain_head.block_number();
+        let raw_blocks: Vec<RawBlock> = serde_json::from_value(
+            client::call(
+                primary_url,
+                "btc2_getRawBlocks",
+                json!([
+                    format!("0x{:x}", head),
+                    format!("0x{:x}", BLOCKS_PER_REQUEST)
+                ]),
+            )
+            .await?,
+        )
+        .map_err(|e| Error::ParseError(e.to_string()))?;
+        if raw_blocks.is_empty() {
+            chain_head.set_sync_status(None);
+            return Ok(());
+        }
+
+        for raw_block in raw_blocks {
+            apply_block(pool, chain_head, evm, sequencer_public_key, raw_block).await?;
+        }
+    }
+}
+
+async fn apply_block(
+    pool: &PgPool,
+    chain_head: &ChainHead,
+    evm: &Evm,
+    sequencer_public_key: &VerifyingKey,
+    raw_block: RawBlock,
+) -> Result<()> {
+    let number = parse_i64(&raw_block.number)?;
+    let timestamp = OffsetDateTime::from_unix_timestamp(parse_i64(&raw_block.timestamp)?)
+        .map_err(|e| Error::ParseError(e.to_string()))?;
+    let timestamp = PrimitiveDateTime::new(timestamp.date(), timestamp.time());
+    // Neither is covered by the signature, so they are checked against the local head
+    // before any transaction runs
+    if number != chain_head.block_number() + 1 {
+        return Err(Error::Error(format!(
+            "Block {} does not follow block {}",
+            number,
+            chain_head.block_number()
+        )));
+    }
+    if chain_head
+        .block()
+        .is_some_and(|head| timestamp < head.timestamp)
+    {
+        return Err(Error::Error(format!(
+            "Block {} is older than its parent",
+            number
+        )));
+    }
+    let hash: [u8; 32] = parse_bytes_like(&raw_block.hash)?.try_into()?;
+    let signature: Option<[u8; 65]> = raw_block
+        .signature
+        .map(|signature| -> Result<[u8; 65]> { Ok(parse_bytes_like(&signature)?.try_into()?) })
+        .transpose()?;
+    let transactions = raw_block
+        .transactions
+        .iter()
+        .map(|raw_transaction| {
+            Ok(TransactionSigned::decode_rlp_legacy_transaction(
+                &mut &parse_bytes_like(raw_transaction)?[..],
+            )?)
+        })
+        .collect::<Result<Vec<TransactionSigned>>>()?;
+
+    if block_hash(transactions.clone()) != hash {
+        return Err(Error::Error(format!(
+            "Block {} does not match its transactions",
+            number
+        )));
+    }
+    sequencer::verify(sequencer_public_key, &hash, signature)?;
+
+    // Transactions that were applied before an interrupted sync are not re-run
+    let mut transaction_ids = vec![];
+    for transaction in &transactions {
+        transaction_ids.push(
+            match get_transaction_id_by_hash(pool, transaction.hash().into()).await? {
+                Some(transaction_id) => transaction_id,
+                None => evm.run_transaction(transaction).await?,
+            },
+        );
+    }
+
+    let block = Block {
+        number,
+        hash,
+        timestamp,
+        signature,
+    };
+    let mut tx = pool.begin().await?;
+    let parent_hash = get_head_block(&mut *tx, i64::MAX)
+        .await?
+        .map(|block| block.hash)
+        .unwrap_or_default();
+    import_block(&mut *tx, &block).await?;
+    update_transactions_block_number(&mut *tx, transaction_ids, number).await?;
+    tx.commit().await?;
+    chain_head.set(block.clone());
+    events::publish(Event::NewHead { block, parent_hash });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RawBlock;
+    use crate::{
+        block_producer::block_hash,
+        chain_head::ChainHead,
+        constants::LAST_LEGACY_BLOCK_NUMBER,
+        db,
+        error::Error,
+        evm::{Evm, TransactionSigned},
+        sequencer,
+    };
+    use k256::ecdsa::SigningKey;
+    use sqlx::PgPool;
+
+    // Signed for chain id 203, so it only keeps its hash if the original bytes are stored
+    const RAW_TRANSACTION: &str = "f8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb";
+
+    fn transaction() -> TransactionSigned {
+        TransactionSigned::decode_rlp_legacy_transaction(
+            &mut &hex::decode(RAW_TRANSACTION).unwrap()[..],
+        )
+        .unwrap()
+    }
+
+    fn raw_block(hash: [u8; 32], signing_key: &SigningKey) -> RawBlock {
+        RawBlock {
+            number: format!("0x{:x}", LAST_LEGACY_BLOCK_NUMBER + 1),
+            hash: format!("0x{}", hex::encode(hash)),
+            timestamp: "0x6650e3c0".to_string(),
+            signature: Some(format!(
+                "0x{}",
+                hex::encode(sequencer::sign_with(signing_key, &hash).unwrap())
+            )),
+            transactions: vec![format!("0x{}", RAW_TRANSACTION)],
+        }
+    }
+
+    #[sqlx::test]
+    async fn apply_block(pool: PgPool) -> sqlx::Result<()> {
+        let signing_key = SigningKey::from_slice(&[1; 32]).unwrap();
+        let evm = Evm::new(pool.clone());
+        evm.deposit(
+            hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625").into(),
+            100000000,
+        )
+        .await;
+        let hash = block_hash(vec![transaction()]);
+        let chain_head = ChainHead::default();
+
+        super::apply_block(
+            &pool,
+            &chain_head,
+            &evm,
+            signing_key.verifying_key(),
+            raw_block(hash, &signing_key),
+        )
+        .await
+        .unwrap();
+
+        let block = db::get_block_by_number(&pool, LAST_LEGACY_BLOCK_NUMBER + 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.hash, hash);
+        assert_eq!(chain_head.block_number(), block.number);
+        let transactions = db::get_transactions_by_block_number(&pool, Some(block.number))
+            .await
+            .unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].1, transaction());
+        assert_eq!(
+            evm.get_balance(hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625"))
+                .await,
+            Some(50000000)
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn apply_block_with_wrong_hash(pool: PgPool) -> sqlx::Result<()> {
+        let signing_key = SigningKey::from_slice(&[1; 32]).unwrap();
+        let evm = Evm::new(pool.clone());
+
+        let result = super::apply_block(
+            &pool,
+            &ChainHead::default(),
+            &evm,
+            signing_key.verifying_key(),
+            raw_block([0; 32], &signing_key),
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::Error(_))));
+        assert_eq!(
+            db::get_transaction_id_by_hash(&pool, transaction().hash().into())
+                .await
+                .unwrap(),
+            None
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn apply_block_out_of_order(pool: PgPool) -> sqlx::Result<()> {
+        let signing_key = SigningKey::from_slice(&[1; 32]).unwrap();
+        let evm = Evm::new(pool.clone());
+        let hash = block_hash(vec![transaction()]);
+        let mut raw_block = raw_block(hash, &signing_key);
+        raw_block.number = format!("0x{:x}", LAST_LEGACY_BLOCK_NUMBER + 2);
+
+        let result = super::apply_block(
+            &pool,
+            &ChainHead::default(),
+            &evm,
+            signing_key.verifying_key(),
+            raw_block,
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::Error(_))));
+        assert_eq!(
+            db::get_transaction_id_by_hash(&pool, transaction().hash().into())
+                .await
+                .unwrap(),
+            None
+        );
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn apply_block_with_wrong_signer(pool: PgPool) -> sqlx::Result<()> {
+        let signing_key = SigningKey::from_slice(&[1; 32]).unwrap();
+        let other_key = SigningKey::from_slice(&[2; 32]).unwrap();
+        let evm = Evm::new(pool.clone());
+
+        let result = super::apply_block(
+            &pool,
+            &ChainHead::default(),
+            &evm,
+            signing_key.verifying_key(),
+            raw_block(block_hash(vec![transaction()]), &other_key),
+        )
+        .await;
+
+        assert!(matches!(result, Err(Error::InvalidSignature)));
+        assert_eq!(
+            db::get_transaction_id_by_hash(&pool, transaction().hash().into())
+                .await
+                .unwrap(),
+            None
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    bitcoin_legacy::utxos,
+    constants::{Env, ENV, MIGRATOR},
+    control,
+    error::{Error, Result},
+};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::{json, Value};
+use sqlx::{query_as, types::time::OffsetDateTime, PgPool};
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, AtomicI64, Ordering},
+    time::Duration,
+};
+use tokio::task::spawn_blocking;
+
+// The producer (or follower) counts as stuck after missing this many slots
+const MISSED_SLOTS: u32 = 10;
+const MIN_SLOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+static LAST_SLOT_AT: AtomicI64 = AtomicI64::new(0);
+static CERTIFICATE_LOADED: AtomicBool = AtomicBool::new(false);
+
+// Called by `block_producer` and `follower` every slot, whether or not a block was added
+pub fn record_slot() {
+    LAST_SLOT_AT.store(now_millis(), Ordering::Relaxed);
+}
+
+pub fn set_certificate_loaded() {
+    CERTIFICATE_LOADED.store(true, Ordering::Relaxed);
+}
+
+fn now_millis() -> i64 {
+    (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+// The process is up and serving HTTP
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+pub async fn readyz(State(pool): State<PgPool>) -> impl IntoResponse {
+    let checks = json!({
+        "postgres": check(postgres(&pool).await),
+        "migrations": check(migrations(&pool).await),
+        "legacyUtxos": check(legacy_utxos().await),
+        "blockProduction": check(block_production()),
+        "certificate": check(certificate()),
+    });
+    let ready = checks
+        .as_object()
+        .unwrap()
+        .values()
+        .all(|check| check == "ok");
+
+    (
+        if ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(json!({
+            "status": if ready { "ready" } else { "unavailable" },
+            "checks": checks,
+        })),
+    )
+}
+
+fn check(result: Result<()>) -> String {
+    match result {
+        Ok(()) => "ok".to_string(),
+        Err(err) => err.to_string(),
+    }
+}
+
+// rusqlite blocks, so the check runs off the async workers
+async fn legacy_utxos() -> Result<()> {
+    spawn_blocking(utxos::ping).await?
+}
+
+async fn postgres(pool: &PgPool) -> Result<()> {
+    query_as::<_, (i32,)>("SELECT 1").fetch_one(pool).await?;
+
+    Ok(())
+}
+
+async fn migrations(pool: &PgPool) -> Result<()> {
+    let applied: HashSet<i64> =
+        query_as::<_, (i64,)>("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .map(|(version,)| version)
+            .collect();
+
+    match MIGRATOR
+        .iter()
+        .find(|migration| !applied.contains(&migration.version))
+    {
+        Some(migration) => Err(Error::Error(format!(
+            "Migration {} is not applied",
+            migration.version
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn block_production() -> Result<()> {
+    let timeout = (control::block_policy().block_time * MISSED_SLOTS).max(MIN_SLOT_TIMEOUT);
+    let last_slot_at = LAST_SLOT_AT.load(Ordering::Relaxed);
+    if last_slot_at == 0 {
+        return Err(Error::Error("No slot has been checked yet".to_string()));
+    }
+    let elapsed = now_millis() - last_slot_at;
+    if elapsed > timeout.as_millis() as i64 {
+        return Err(Error::Error(format!(
+            "Last slot was checked {}ms ago",
+            elapsed
+        )));
+    }
+
+    Ok(())
+}
+
+// Only production serves TLS
+fn certificate() -> Result<()> {
+    if matches!(*ENV, Env::Production) && !CERTIFICATE_LOADED.load(Ordering::Relaxed) {
+        return Err(Error::Error("No TLS certificate is loaded".to_string()));
+    }
+
+    Ok(())
+}
@@ -1,30 +1,144 @@
+pub mod anchor;
 pub mod bitcoin_legacy;
 pub mod block_producer;
+pub mod chain_head;
 pub mod constants;
+pub mod control;
 pub mod db;
 mod error;
+pub mod events;
 pub mod evm;
+pub mod follower;
+pub mod health;
+pub mod metrics;
+pub mod rate_limit;
 mod rpc;
+pub mod sequencer;
+pub mod snapshot;
+pub mod verify;
+
+pub use rpc::{admin::AdminCredentials, ipc};
 
 use axum::{
+    extract::{DefaultBodyLimit, FromRef},
     http::{header, method::Method},
-    routing::post,
+    middleware,
+    routing::{get, post},
     Router,
 };
+use chain_head::ChainHead;
 use sqlx::PgPool;
 
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::{
+    cors::{Any, CorsLayer},
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Every router gets its own chain head, `main` shares it with whichever of
+// `block_producer` or `follower` is running
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub chain_head: ChainHead,
+}
+
+impl AppState {
+    pub async fn load(pool: PgPool) -> error::Result<Self> {
+        let chain_head = ChainHead::load(&pool).await?;
+
+        Ok(Self { pool, chain_head })
+    }
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for ChainHead {
+    fn from_ref(state: &AppState) -> Self {
+        state.chain_head.clone()
+    }
+}
 
+// Loads the chain head from `pool`, panics if it can't be read
 pub async fn app(pool: PgPool) -> Router {
+    router(AppState::load(pool).await.unwrap())
+}
+
+pub fn router(state: AppState) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
-        .allow_headers(vec![header::CONTENT_TYPE])
-        .allow_methods(vec![Method::POST]);
+        .allow_headers(vec![
+            header::CONTENT_TYPE,
+            header::HeaderName::from_static(rate_limit::API_KEY_HEADER),
+            header::HeaderName::from_static(REQUEST_ID_HEADER),
+        ])
+        .expose_headers(vec![header::HeaderName::from_static(REQUEST_ID_HEADER)])
+        .allow_methods(vec![Method::GET, Method::POST]);
+
+    traced(
+        Router::new()
+            .route("/", post(rpc::handler).get(rpc::ws::handler))
+            .route("/openrpc.json", get(rpc::openrpc::handler))
+            .route("/healthz", get(health::healthz))
+            .route("/readyz", get(health::readyz))
+            .route("/v1/blocks/:number", get(rpc::rest::block))
+            .route("/v1/tx/:hash", get(rpc::rest::transaction))
+            .route("/v1/address/:address", get(rpc::rest::address))
+            .route("/v1/legacy/:txid/:vout", get(rpc::rest::legacy_output))
+            .layer(middleware::from_fn(rate_limit::limit))
+            .layer(DefaultBodyLimit::max(*constants::MAX_BODY_SIZE)),
+    )
+    .layer(cors)
+    .with_state(state)
+}
+
+// Served on ADMIN_PORT, never on the public port. Metrics are here since
+// rendering them queries the upgrade totals.
+pub fn admin_router(state: AppState, credentials: AdminCredentials) -> Router {
+    traced(
+        Router::new()
+            .route("/", post(rpc::admin::handler))
+            .route("/metrics", get(metrics::handler))
+            .layer(middleware::from_fn_with_state(
+                credentials,
+                rpc::admin::authorize,
+            )),
+    )
+    .with_state(state)
+}
 
-    Router::new()
-        .route("/", post(rpc::handler))
-        .layer(cors)
-        .with_state(pool)
+// Every request gets a span tagged with its X-Request-Id, generated when the
+// client doesn't send one and echoed back in the response
+fn traced(router: Router<AppState>) -> Router<AppState> {
+    router
+        .layer(PropagateRequestIdLayer::new(
+            header::HeaderName::from_static(REQUEST_ID_HEADER),
+        ))
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|request: &axum::extract::Request| {
+                let request_id = request
+                    .headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|request_id| request_id.to_str().ok())
+                    .unwrap_or_default();
+                tracing::info_span!(
+                    "http",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    request_id,
+                )
+            }),
+        )
+        .layer(SetRequestIdLayer::new(
+            header::HeaderName::from_static(REQUEST_ID_HEADER),
+            MakeRequestUuid,
+        ))
 }
 
 #[cfg(test)]
@@ -33,8 +147,10 @@ mod tests {
     use crate::{db::get_balance, evm::Evm};
     use axum::{
         body::Body,
+        extract::connect_info::MockConnectInfo,
         http::{Request, StatusCode},
     };
+    use http_body_util::BodyExt;
     use serde_json::json;
     use sqlx::PgPool;
     use tower::ServiceExt;
@@ -133,6 +249,519 @@ mod tests {
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn batch(pool: PgPool) -> sqlx::Result<()> {
+        let message = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": []},
+            {"jsonrpc": "2.0", "id": 2},
+        ]);
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/json")
+            .uri("/")
+            .body(Body::from(message.to_string()))
+            .unwrap();
+
+        let response = app(pool).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let responses: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(responses.as_array().unwrap().len(), 2);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"], "0xb2");
+        assert_eq!(responses[1]["id"], serde_json::Value::Null);
+        assert!(responses[1]["error"].is_object());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn errors(pool: PgPool) -> sqlx::Result<()> {
+        for (body, id, code) in [
+            ("{", json!(null), -32700),
+            (r#"{"jsonrpc": "2.0", "id": 1}"#, json!(null), -32600),
+            (
+                r#"{"jsonrpc": "2.0", "method": "eth_unknown", "params": [], "id": 1}"#,
+                json!(1),
+                -32601,
+            ),
+            (
+                r#"{"jsonrpc": "2.0", "method": "eth_getBalance", "params": ["0x00", "latest"], "id": 2}"#,
+                json!(2),
+                -32602,
+            ),
+            (
+                r#"{"jsonrpc": "2.0", "method": "eth_getTransactionByHash", "params": ["0xzz"], "id": 3}"#,
+                json!(3),
+                -32602,
+            ),
+        ] {
+            let request = Request::builder()
+                .method("POST")
+                .header("content-type", "application/json")
+                .uri("/")
+                .body(Body::from(body))
+                .unwrap();
+
+            let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(response["jsonrpc"], "2.0");
+            assert_eq!(response["id"], id);
+            assert_eq!(response["error"]["code"], code);
+        }
+
+        // Nothing was deposited, so the ledger rejects the transfer
+        let response = rpc(
+            &pool,
+            "eth_sendRawTransaction",
+            json!(["0xf8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb"]),
+        )
+        .await;
+        // Rejected before execution, like geth's "insufficient funds for gas * price + value"
+        assert_eq!(response["error"]["code"], -32000);
+        assert_eq!(response["error"]["message"], "Insufficient funds");
+        assert_eq!(response["error"]["data"], json!(null));
+
+        Ok(())
+    }
+
+    async fn rpc(pool: &PgPool, method: &str, params: serde_json::Value) -> serde_json::Value {
+        let message = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/json")
+            .uri("/")
+            .body(Body::from(message.to_string()))
+            .unwrap();
+
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[sqlx::test]
+    async fn get_transaction_by_hash(pool: PgPool) -> sqlx::Result<()> {
+        let evm: Evm = Evm::new(pool.clone());
+        evm.deposit(
+            hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625").into(),
+            100000000,
+        )
+        .await;
+        let hash = rpc(
+            &pool,
+            "eth_sendRawTransaction",
+            json!(["0xf8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb"]),
+        )
+        .await["result"]
+            .clone();
+
+        let transaction = &rpc(&pool, "eth_getTransactionByHash", json!([hash])).await["result"];
+        assert_eq!(transaction["hash"], hash);
+        assert_eq!(
+            transaction["from"],
+            "0xf204ee5596cabc6ec60e5e92fd412ea7f856b625"
+        );
+        assert_eq!(
+            transaction["to"],
+            "0x3073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee"
+        );
+        assert_eq!(transaction["nonce"], "0x1");
+        assert_eq!(transaction["blockNumber"], serde_json::Value::Null);
+
+        // Not mined into a block yet
+        let receipt = &rpc(&pool, "eth_getTransactionReceipt", json!([hash])).await["result"];
+        assert_eq!(*receipt, serde_json::Value::Null);
+
+        let unknown = json!([format!("0x{}", hex::encode([0; 32]))]);
+        let response = rpc(&pool, "eth_getTransactionByHash", unknown).await;
+        assert_eq!(response["result"], serde_json::Value::Null);
+
+        Ok(())
+    }
+
+    // The calls wallets such as MetaMask make when a network is added
+    #[sqlx::test]
+    async fn add_network(pool: PgPool) -> sqlx::Result<()> {
+        let address = "0xf204ee5596cabc6ec60e5e92fd412ea7f856b625";
+        for (method, params) in [
+            ("eth_chainId", json!([])),
+            ("net_version", json!([])),
+            ("web3_clientVersion", json!([])),
+            ("eth_blockNumber", json!([])),
+            ("eth_getBlockByNumber", json!(["latest", false])),
+            ("eth_syncing", json!([])),
+            ("net_listening", json!([])),
+            ("net_peerCount", json!([])),
+            ("eth_accounts", json!([])),
+            ("eth_gasPrice", json!([])),
+            ("eth_maxPriorityFeePerGas", json!([])),
+            ("eth_feeHistory", json!(["0x5", "latest", [10, 50]])),
+            ("eth_feeHistory", json!([5, "latest"])),
+            ("eth_getBalance", json!([address, "latest"])),
+            ("eth_getTransactionCount", json!([address, "pending"])),
+            ("eth_getCode", json!([address])),
+            (
+                "eth_estimateGas",
+                json!([{"from": address, "to": address, "value": "0x0"}]),
+            ),
+            ("eth_getBlockTransactionCountByNumber", json!(["latest"])),
+            (
+                "eth_getTransactionByBlockNumberAndIndex",
+                json!(["latest", "0x0"]),
+            ),
+            ("eth_getBlockReceipts", json!(["latest"])),
+            ("web3_sha3", json!(["0x68656c6c6f20776f726c64"])),
+        ] {
+            let response = rpc(&pool, method, params).await;
+            assert!(
+                response.get("result").is_some(),
+                "{} failed: {}",
+                method,
+                response
+            );
+        }
+
+        assert_eq!(
+            rpc(&pool, "web3_sha3", json!(["0x68656c6c6f20776f726c64"])).await["result"],
+            "0x47173285a8d7341e5e972fc677286384f802f8ef42a5ec5f03bbfa254cb01fad"
+        );
+        let fee_history = rpc(&pool, "eth_feeHistory", json!(["0x5", "latest", [10, 50]])).await;
+        assert_eq!(fee_history["result"]["baseFeePerGas"][0], "0x0");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn earliest_block(pool: PgPool) -> sqlx::Result<()> {
+        let earliest = json!(["earliest", false]);
+        assert_eq!(
+            rpc(&pool, "eth_getBlockByNumber", earliest.clone()).await["result"],
+            serde_json::Value::Null
+        );
+
+        // The head of an imported snapshot, the blocks before it aren't stored
+        db::import_block(
+            &pool,
+            &db::Block {
+                number: constants::LAST_LEGACY_BLOCK_NUMBER + 5,
+                hash: [1; 32],
+                timestamp: constants::LAST_LEGACY_BLOCK_TIMESTAMP,
+                signature: None,
+            },
+        )
+        .await
+        .unwrap();
+        db::insert_block(&pool, [2; 32], None).await.unwrap();
+        assert_eq!(
+            rpc(&pool, "eth_getBlockByNumber", earliest).await["result"]["number"],
+            format!("0x{:x}", constants::LAST_LEGACY_BLOCK_NUMBER + 5)
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn get_ledger(pool: PgPool) -> sqlx::Result<()> {
+        let evm: Evm = Evm::new(pool.clone());
+        let address = "0xf204ee5596cabc6ec60e5e92fd412ea7f856b625";
+        evm.deposit(
+            hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625").into(),
+            100000000,
+        )
+        .await;
+        let hash = rpc(
+            &pool,
+            "eth_sendRawTransaction",
+            json!(["0xf8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb"]),
+        )
+        .await["result"]
+            .clone();
+
+        // Without options the history is a plain array, as before pagination
+        let ledger = rpc(&pool, "btc2_getLedger", json!([address])).await;
+        let entries = ledger["result"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["creditor"], address);
+        assert_eq!(
+            entries[0]["debtor"],
+            "0x3073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee"
+        );
+        assert_eq!(entries[0]["value"], "0x6f05b59d3b20000");
+
+        let ledger = rpc(&pool, "btc2_getLedger", json!([address, {"limit": 1}])).await;
+        let entries = ledger["result"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["transactionHash"], hash);
+        assert_eq!(entries[0]["type"], "transfer");
+        assert_eq!(entries[0]["direction"], "out");
+        assert_eq!(entries[0]["blockNumber"], serde_json::Value::Null);
+
+        let cursor = ledger["result"]["nextCursor"].clone();
+        let ledger = rpc(
+            &pool,
+            "btc2_getLedger",
+            json!([address, {"cursor": cursor}]),
+        )
+        .await;
+        assert_eq!(ledger["result"]["entries"], json!([]));
+        assert_eq!(ledger["result"]["nextCursor"], serde_json::Value::Null);
+
+        let ledger = rpc(
+            &pool,
+            "btc2_getLedger",
+            json!([address, {"direction": "in"}]),
+        )
+        .await;
+        assert_eq!(ledger["result"]["entries"], json!([]));
+
+        let ledger = rpc(&pool, "btc2_getLedger", json!([address, {"page": 2}])).await;
+        assert_eq!(ledger["error"]["code"], -32602);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn build_upgrade_message(pool: PgPool) -> sqlx::Result<()> {
+        let destination = "0xf204ee5596cabc6ec60e5e92fd412ea7f856b625";
+        let outpoint = json!({
+            "txid": "49168ebc826a82cc84c0139660d9bafb919a6a51d2f01bf31629896061e394d0",
+            "vout": 0,
+        });
+
+        let built = rpc(
+            &pool,
+            "btc2_buildUpgradeMessage",
+            json!([destination, [outpoint]]),
+        )
+        .await;
+        assert_eq!(built["result"]["amount"], "0x21449");
+        assert_eq!(
+            built["result"]["to"],
+            "0x0000000000000000000000000000000000000000"
+        );
+
+        let duplicate = rpc(
+            &pool,
+            "btc2_buildUpgradeMessage",
+            json!([destination, [outpoint, outpoint]]),
+        )
+        .await;
+        assert_eq!(duplicate["error"]["code"], -32602);
+
+        // Past what `spent_legacy_outputs` can store
+        let out_of_range = rpc(
+            &pool,
+            "btc2_buildUpgradeMessage",
+            json!([destination, [{"txid": outpoint["txid"], "vout": 40000}]]),
+        )
+        .await;
+        assert_eq!(out_of_range["error"]["code"], -32602);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn upgrade_stats(pool: PgPool) -> sqlx::Result<()> {
+        let _evm: Evm = Evm::new(pool.clone());
+        rpc(
+            &pool,
+            "eth_sendRawTransaction",
+            json!(["0xf90227068082520894000000000000000000000000000000000000000080b901c4e60b060d0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000014000000000000000000000000000000000000000000000000000000000000000cd416374696f6e3a20557067726164650a44657374696e6174696f6e20436861696e2049443a203230330a44657374696e6174696f6e20416464726573733a203078663230344545353539364341626336456336306535653932466434313245413766383536623632350a496e707574733a0a20202d0a20202020486173683a20343931363865626338323661383263633834633031333936363064396261666239313961366135316432663031626633313632393839363036316533393464300a20202020496e6465783a203000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004120239ff874c5e9bcdccf84c0e68333d0337f43847d54d56a3b1e339b4b59a975fb59d0741f8857b9467d00ca418d40f3a7f02e4fa4cbd68daf5c29163310724fdf00000000000000000000000000000000000000000000000000000000000000820188a0af7479b422eefc7e6f3051922e63ceb5330fdd08da66c22d5619f2ba514e24faa01ff98a79df5b2b48007e99e810e0d1d6aeed931993401880efef5dae7f46d790"]),
+        )
+        .await;
+
+        let supply = rpc(&pool, "btc2_getSupply", json!([])).await;
+        assert_eq!(supply["result"]["upgraded"], "0x21449");
+        assert_eq!(
+            supply["result"]["legacyUnclaimed"],
+            format!("0x{:x}", constants::LEGACY_SUPPLY - 136265)
+        );
+
+        let expected = json!({
+            "upgraded": "0x21449",
+            "outpoints": "0x1",
+            "recipients": "0x1",
+            "byScriptType": {"p2wpkh": {"outpoints": "0x1", "amount": "0x21449"}},
+            // Still pending
+            "series": [],
+        });
+        let stats = rpc(&pool, "btc2_getUpgradeStats", json!([])).await;
+        assert_eq!(stats["result"], expected);
+
+        // As if it was spent before details were recorded, the stats stay read only
+        // until `index-legacy-utxos` fills them in
+        sqlx::query("UPDATE spent_legacy_outputs SET script_type = NULL, amount = NULL")
+            .execute(&pool)
+            .await?;
+        let stats = rpc(&pool, "btc2_getUpgradeStats", json!([])).await;
+        assert_eq!(stats["result"]["byScriptType"], json!({}));
+        assert_eq!(
+            bitcoin_legacy::utxos::fill_spent_output_details(&pool)
+                .await
+                .unwrap(),
+            1
+        );
+        let stats = rpc(&pool, "btc2_getUpgradeStats", json!([])).await;
+        assert_eq!(stats["result"], expected);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn rest(pool: PgPool) -> sqlx::Result<()> {
+        let evm: Evm = Evm::new(pool.clone());
+        evm.deposit(
+            hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625").into(),
+            100000000,
+        )
+        .await;
+        let hash = rpc(
+            &pool,
+            "eth_sendRawTransaction",
+            json!(["0xf8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb"]),
+        )
+        .await["result"]
+            .clone();
+        let uri = format!("/v1/tx/{}", hash.as_str().unwrap());
+
+        let request = Request::builder().uri(&uri).body(Body::empty()).unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()["etag"].clone();
+        assert!(response.headers().contains_key(REQUEST_ID_HEADER));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let transaction: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(transaction["nonce"], 1);
+        assert_eq!(transaction["value"], "500000000000000000");
+        assert_eq!(transaction["gas"], 21000);
+
+        let request = Request::builder()
+            .uri(&uri)
+            .header("if-none-match", etag)
+            .body(Body::empty())
+            .unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let request = Request::builder()
+            .uri("/v1/address/0xf204ee5596cabc6ec60e5e92fd412ea7f856b625?limit=1")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let address: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(address["nonce"], 1);
+        assert_eq!(address["balance"], "500000000000000000");
+        assert_eq!(address["ledger"]["entries"][0]["transactionHash"], hash);
+
+        // Never credited, so it has no account row
+        let request = Request::builder()
+            .uri("/v1/address/0x5050a4f4b3f9338c3472dcc01a87c76a144b3c9c")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let address: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(address["balance"], "0");
+
+        let request = Request::builder()
+            .uri("/v1/blocks/840000")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The head is loaded with the router, not only by the block producer
+        let block = db::insert_block(&pool, [1; 32], None).await.unwrap();
+        let request = Request::builder()
+            .uri("/v1/blocks/latest")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let latest: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(latest["number"], block.number);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn health(pool: PgPool) -> sqlx::Result<()> {
+        let request = Request::builder()
+            .uri("/healthz")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        health::record_slot();
+        let request = Request::builder()
+            .uri("/readyz")
+            .body(Body::empty())
+            .unwrap();
+        let response = app(pool.clone()).await.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let readiness: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(readiness["checks"]["postgres"], "ok");
+        assert_eq!(readiness["checks"]["migrations"], "ok");
+        assert_eq!(readiness["checks"]["blockProduction"], "ok");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn rate_limit(pool: PgPool) -> sqlx::Result<()> {
+        // The limiter is process wide, so this test has an address to itself
+        let app = app(pool)
+            .await
+            .layer(MockConnectInfo(std::net::SocketAddr::from((
+                [192, 0, 2, 45],
+                0,
+            ))));
+        // Charged in full but rejected before touching the database, so the
+        // bucket can't refill much before the next request
+        let batch: Vec<serde_json::Value> = (0..*constants::RATE_LIMIT / 20)
+            .map(|id| {
+                json!({"jsonrpc": "2.0", "method": "btc2_getUpgradeStats", "params": [id], "id": id})
+            })
+            .collect();
+        let request = Request::builder()
+            .method("POST")
+            .header("content-type", "application/json")
+            .uri("/")
+            .body(Body::from(json!(batch).to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // REST routes share the quota
+        let request = Request::builder()
+            .uri("/v1/address/0xf204ee5596cabc6ec60e5e92fd412ea7f856b625")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["code"], -32005);
+
+        let request = Request::builder()
+            .uri("/healthz")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
     #[sqlx::test]
     async fn get_transactions(pool: PgPool) -> sqlx::Result<()> {
         let evm: Evm = Evm::new(pool.clone());
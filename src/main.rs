@@ -1,30 +1,130 @@
 use bitcoin2::{
-    block_producer,
-    constants::{Env, ENV, LETS_ENCRYPT_DOMAINS, LETS_ENCRYPT_EMAILS, MIGRATOR, PORT},
+    anchor, bitcoin_legacy, block_producer,
+    constants::{
+        Env, ADMIN_BIND, ADMIN_PORT, ANCHOR_DESCRIPTOR, BITCOIND_URL, ENV, IPC_PATH,
+        LETS_ENCRYPT_DOMAINS, LETS_ENCRYPT_EMAILS, MIGRATOR, PORT, PRIMARY_URL,
+    },
+    follower, health, ipc,
+    snapshot::{self, SnapshotFile},
+    verify::verify_chain,
+    AdminCredentials, AppState,
 };
 use dotenv::dotenv;
-use rustls_acme::{caches::DirCache, AcmeConfig};
+use rustls_acme::{caches::DirCache, AcmeConfig, EventOk};
 use sqlx::postgres::PgPoolOptions;
-use std::{env, net::Ipv6Addr, path::PathBuf};
+use std::{
+    env,
+    fmt::Display,
+    future::Future,
+    net::{Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    process::exit,
+};
 use tokio::spawn;
 use tokio_stream::StreamExt;
+use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     dotenv().ok();
+    // RUST_LOG, e.g. `info,bitcoin2=debug`
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or(EnvFilter::new("info")))
+        .init();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let pool = PgPoolOptions::new().connect(&database_url).await?;
 
     MIGRATOR.run(&pool).await?;
 
-    spawn({
-        let pool = pool.clone();
-        async move {
-            block_producer::start(pool.clone()).await.unwrap();
+    match env::args().nth(1).as_deref() {
+        Some("verify-chain") => {
+            if let Some(divergence) = verify_chain(&pool).await? {
+                println!("divergence: {}", divergence);
+                exit(1);
+            }
+            println!("chain verified");
+            return Ok(());
         }
-    });
+        Some("export-snapshot") => {
+            let path = env::args()
+                .nth(2)
+                .expect("usage: export-snapshot <path> [height]");
+            let height = env::args()
+                .nth(3)
+                .map(|height| height.parse())
+                .transpose()?;
+            let file = SnapshotFile::new(snapshot::export(&pool, height).await?)?;
+            file.write(Path::new(&path))?;
+            println!(
+                "exported block {} to {} ({})",
+                file.snapshot.height, path, file.checksum
+            );
+            return Ok(());
+        }
+        Some("import-snapshot") => {
+            let path = env::args().nth(2).expect("usage: import-snapshot <path>");
+            let file = SnapshotFile::read(Path::new(&path))?;
+            snapshot::import(&pool, &file.snapshot).await?;
+            println!("imported block {} from {}", file.snapshot.height, path);
+            return Ok(());
+        }
+        Some("index-legacy-utxos") => {
+            let count = bitcoin_legacy::utxos::index_script_hashes()?;
+            println!("indexed {} legacy outputs by script hash", count);
+            let count = bitcoin_legacy::utxos::fill_spent_output_details(&pool).await?;
+            println!("recorded details of {} spent legacy outputs", count);
+            return Ok(());
+        }
+        Some(command) => {
+            println!("unknown command: {}", command);
+            exit(2);
+        }
+        None => (),
+    }
+
+    let state = AppState::load(pool.clone()).await?;
+    if let Some(primary_url) = PRIMARY_URL.clone() {
+        spawn_task(
+            "follower",
+            follower::start(pool.clone(), state.chain_head.clone(), primary_url),
+        );
+    } else {
+        spawn_task(
+            "block_producer",
+            block_producer::start(pool.clone(), state.chain_head.clone()),
+        );
+    }
+    if let (None, Some(bitcoind_url), Some(descriptor)) = (
+        PRIMARY_URL.as_ref(),
+        BITCOIND_URL.clone(),
+        ANCHOR_DESCRIPTOR.clone(),
+    ) {
+        spawn_task(
+            "anchor",
+            anchor::start(pool.clone(), bitcoind_url, descriptor),
+        );
+    }
+    if let Some(ipc_path) = IPC_PATH.clone() {
+        // Only the node's user can connect
+        spawn_task(
+            "ipc",
+            ipc::serve(state.clone(), PathBuf::from(ipc_path), 0o600),
+        );
+    }
+    if let Some(admin_port) = *ADMIN_PORT {
+        let credentials = AdminCredentials::from_env();
+        if credentials.is_empty() {
+            panic!("ADMIN_PORT requires ADMIN_TOKEN or ADMIN_JWT_SECRET");
+        }
+        let listener = tokio::net::TcpListener::bind((*ADMIN_BIND, admin_port)).await?;
+        let admin_app = bitcoin2::admin_router(state.clone(), credentials);
+        spawn(async move {
+            axum::serve(listener, admin_app).await.unwrap();
+        });
+    }
     let addr = (Ipv6Addr::UNSPECIFIED, *PORT);
-    let app = bitcoin2::app(pool).await;
+    let app = bitcoin2::router(state);
     if matches!(*ENV, Env::Production) {
         let mut state = AcmeConfig::new(LETS_ENCRYPT_DOMAINS.clone())
             .contact(LETS_ENCRYPT_EMAILS.iter().map(|e| format!("mailto:{}", e)))
@@ -36,19 +136,42 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         tokio::spawn(async move {
             loop {
                 match state.next().await.unwrap() {
-                    Ok(ok) => println!("event: {:?}", ok),
-                    Err(err) => println!("error: {:?}", err),
+                    Ok(ok) => {
+                        if matches!(ok, EventOk::DeployedCachedCert | EventOk::DeployedNewCert) {
+                            health::set_certificate_loaded();
+                        }
+                        tracing::info!(event = ?ok, "acme");
+                    }
+                    Err(err) => tracing::error!(error = ?err, "acme"),
                 }
             }
         });
         axum_server::bind(addr.into())
             .acceptor(acceptor)
-            .serve(app.into_make_service())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
             .await
             .unwrap();
     } else {
         let listener = tokio::net::TcpListener::bind(addr).await?;
-        axum::serve(listener, app).await?
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await?
     };
     Ok(())
 }
+
+// Background tasks retry their own transient errors, so one that returns can't
+// continue and the node exits rather than run without it
+fn spawn_task<E: Display + Send + 'static>(
+    name: &'static str,
+    task: impl Future<Output = Result<(), E>> + Send + 'static,
+) {
+    spawn(async move {
+        if let Err(err) = task.await {
+            tracing::error!(task = name, error = %err, "task failed");
+            exit(1);
+        }
+    });
+}
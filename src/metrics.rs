@@ -0,0 +1,317 @@
+use crate::{
+    chain_head::ChainHead,
+    db,
+    error::{Error, Result},
+    rate_limit,
+};
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use lazy_static::lazy_static;
+use sqlx::PgPool;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+// https://prometheus.io/docs/instrumenting/exposition_formats/
+
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+const BLOCK_SIZE_BUCKETS: &[f64] = &[1.0, 10.0, 100.0, 1000.0, 10000.0];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bucket, count) in self.buckets.iter().zip(self.counts.iter_mut()) {
+            if value <= *bucket {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        for (bucket, count) in self.buckets.iter().zip(&self.counts) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bucket, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug)]
+struct Metrics {
+    requests: BTreeMap<&'static str, Histogram>,
+    errors: BTreeMap<&'static str, u64>,
+    block_production: Histogram,
+    block_size: Histogram,
+}
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+        requests: BTreeMap::new(),
+        errors: BTreeMap::new(),
+        block_production: Histogram::new(LATENCY_BUCKETS),
+        block_size: Histogram::new(BLOCK_SIZE_BUCKETS),
+    });
+}
+
+static TRANSACTIONS_SUBMITTED: AtomicU64 = AtomicU64::new(0);
+static TRANSACTIONS_ACCEPTED: AtomicU64 = AtomicU64::new(0);
+static TRANSACTIONS_REJECTED: AtomicU64 = AtomicU64::new(0);
+
+// `method` is a name from `methods::METHODS` so the number of labels is bounded
+pub fn record_request(method: &'static str, duration: Duration, error: Option<&Error>) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics
+        .requests
+        .entry(method)
+        .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+        .observe(duration.as_secs_f64());
+    if let Some(error) = error {
+        *metrics.errors.entry(error.name()).or_default() += 1;
+    }
+}
+
+// For calls rejected before a method ran, like invalid JSON, unknown methods,
+// rate limited or failed forwards, by the transport that rejected them
+pub fn record_error(error: &Error) {
+    let mut metrics = METRICS.lock().unwrap();
+    *metrics.errors.entry(error.name()).or_default() += 1;
+}
+
+pub fn record_transaction(accepted: bool) {
+    TRANSACTIONS_SUBMITTED.fetch_add(1, Ordering::Relaxed);
+    if accepted {
+        TRANSACTIONS_ACCEPTED.fetch_add(1, Ordering::Relaxed);
+    } else {
+        TRANSACTIONS_REJECTED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn record_block(duration: Duration, transactions: usize) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.block_production.observe(duration.as_secs_f64());
+    metrics.block_size.observe(transactions as f64);
+}
+
+pub async fn handler(
+    State(pool): State<PgPool>,
+    State(chain_head): State<ChainHead>,
+) -> axum::response::Result<Response> {
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&pool, &chain_head).await?,
+    )
+        .into_response())
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+pub async fn render(pool: &PgPool, chain_head: &ChainHead) -> Result<String> {
+    let mempool_size = db::get_pending_transaction_count(pool).await?;
+    let upgrades = db::get_upgrade_totals(pool).await?;
+    let rate_limit = rate_limit::counters();
+    let mut out = String::new();
+    {
+        let metrics = METRICS.lock().unwrap();
+
+        metric(
+            &mut out,
+            "bitcoin2_rpc_request_duration_seconds",
+            "histogram",
+            "JSON-RPC calls by method",
+        );
+        for (method, histogram) in &metrics.requests {
+            histogram.render(
+                &mut out,
+                "bitcoin2_rpc_request_duration_seconds",
+                &format!("method=\"{}\"", method),
+            );
+        }
+        metric(
+            &mut out,
+            "bitcoin2_rpc_errors_total",
+            "counter",
+            "Failed JSON-RPC calls by error",
+        );
+        for (error, count) in &metrics.errors {
+            let _ = writeln!(
+                out,
+                "bitcoin2_rpc_errors_total{{error=\"{}\"}} {}",
+                error, count
+            );
+        }
+        metric(
+            &mut out,
+            "bitcoin2_block_production_seconds",
+            "histogram",
+            "Time to build, sign and store a block",
+        );
+        metrics
+            .block_production
+            .render(&mut out, "bitcoin2_block_production_seconds", "");
+        metric(
+            &mut out,
+            "bitcoin2_block_transactions",
+            "histogram",
+            "Transactions per produced block",
+        );
+        metrics
+            .block_size
+            .render(&mut out, "bitcoin2_block_transactions", "");
+    }
+
+    for (name, kind, help, value) in [
+        (
+            "bitcoin2_transactions_submitted_total",
+            "counter",
+            "Raw transactions submitted",
+            TRANSACTIONS_SUBMITTED.load(Ordering::Relaxed),
+        ),
+        (
+            "bitcoin2_transactions_accepted_total",
+            "counter",
+            "Raw transactions accepted into the mempool",
+            TRANSACTIONS_ACCEPTED.load(Ordering::Relaxed),
+        ),
+        (
+            "bitcoin2_transactions_rejected_total",
+            "counter",
+            "Raw transactions rejected",
+            TRANSACTIONS_REJECTED.load(Ordering::Relaxed),
+        ),
+        (
+            "bitcoin2_head_block_number",
+            "gauge",
+            "Number of the latest block",
+            chain_head.block_number() as u64,
+        ),
+        (
+            "bitcoin2_mempool_transactions",
+            "gauge",
+            "Transactions waiting for a block",
+            mempool_size as u64,
+        ),
+        (
+            "bitcoin2_upgrades_total",
+            "counter",
+            "Legacy outpoints upgraded",
+            upgrades.outpoints as u64,
+        ),
+        (
+            "bitcoin2_upgraded_satoshis_total",
+            "counter",
+            "Satoshis upgraded from the legacy chain",
+            upgrades.upgraded as u64,
+        ),
+        (
+            "bitcoin2_db_connections",
+            "gauge",
+            "Open Postgres connections",
+            pool.size() as u64,
+        ),
+        (
+            "bitcoin2_db_idle_connections",
+            "gauge",
+            "Idle Postgres connections",
+            pool.num_idle() as u64,
+        ),
+        (
+            "bitcoin2_db_max_connections",
+            "gauge",
+            "Postgres connection limit",
+            pool.options().get_max_connections() as u64,
+        ),
+        (
+            "bitcoin2_rate_limit_allowed_total",
+            "counter",
+            "Requests within their client's quota",
+            rate_limit.allowed,
+        ),
+        (
+            "bitcoin2_rate_limit_limited_total",
+            "counter",
+            "Requests rejected with 429",
+            rate_limit.limited,
+        ),
+        (
+            "bitcoin2_rate_limit_clients",
+            "gauge",
+            "Clients with a tracked quota",
+            rate_limit.tracked_clients,
+        ),
+    ] {
+        metric(&mut out, name, kind, help);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::new(&[1.0, 10.0]);
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+        histogram.observe(50.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "size", "method=\"eth_chainId\"");
+        assert_eq!(
+            out,
+            "size_bucket{method=\"eth_chainId\",le=\"1\"} 1
+size_bucket{method=\"eth_chainId\",le=\"10\"} 2
+size_bucket{method=\"eth_chainId\",le=\"+Inf\"} 3
+size_sum{method=\"eth_chainId\"} 55.5
+size_count{method=\"eth_chainId\"} 3
+"
+        );
+    }
+}
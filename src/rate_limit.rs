@@ -0,0 +1,291 @@
+use crate::{
+    constants::{API_KEYS, RATE_LIMIT},
+    error::{Error, Result},
+    metrics,
+};
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request},
+    http::{HeaderMap, Method},
+    middleware::Next,
+    response::Response,
+};
+use lazy_static::lazy_static;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+// The least recently seen client is dropped to make room past this many
+const MAX_TRACKED_CLIENTS: usize = 10000;
+// Buckets hold a minute's worth of units, so one left alone this long is full
+const REFILL_TIME: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Client {
+    ApiKey(String),
+    Ip(IpAddr),
+}
+
+impl Client {
+    // Unknown API keys fall back to the caller's IP quota. `None` when the
+    // router is served without connect info, like in the tests' `oneshot`
+    // calls, since limiting every caller as one would let any of them lock
+    // out the rest
+    pub fn new(connect_info: Option<ConnectInfo<SocketAddr>>, headers: &HeaderMap) -> Option<Self> {
+        match headers
+            .get(API_KEY_HEADER)
+            .and_then(|key| key.to_str().ok())
+        {
+            Some(key) if API_KEYS.contains_key(key) => Some(Self::ApiKey(key.to_string())),
+            _ => connect_info.map(|ConnectInfo(addr)| Self::Ip(network(addr.ip()))),
+        }
+    }
+
+    // Units per minute, 0 means unlimited
+    fn limit(&self) -> u64 {
+        match self {
+            Self::ApiKey(key) => API_KEYS[key],
+            Self::Ip(_) => *RATE_LIMIT,
+        }
+    }
+}
+
+// IPv6 hosts are usually handed a whole /64, so one client is one /64 rather
+// than an address it can change on every request. The server binds `::`, so
+// IPv4 clients arrive as mapped addresses.
+fn network(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & (u128::MAX << 64))),
+        ip => ip,
+    }
+}
+
+// Writes take a Postgres transaction and the `Evm` mutex, history and stats
+// queries can scan large parts of the ledger
+pub fn cost(method: &str) -> u64 {
+    match method {
+        "eth_sendRawTransaction" => 10,
+        "btc2_getUpgradeStats" => 20,
+        "btc2_getLedger" | "btc2_getLegacyBalance" | "btc2_getRawBlocks" => 5,
+        "eth_getBlockReceipts" | "eth_feeHistory" => 5,
+        _ => 1,
+    }
+}
+
+// REST routes cost what the calls they stand in for do
+pub fn route_cost(method: &Method, path: &str) -> u64 {
+    match (method, path) {
+        // Charged per call by `rpc::handler` and per message by `rpc::ws`
+        (&Method::POST, "/") => 0,
+        // Probes come from the same few addresses all the time
+        (_, "/healthz" | "/readyz") => 0,
+        (_, "/v1/blocks/:number") => cost("eth_getBlockByNumber"),
+        (_, "/v1/tx/:hash") => cost("eth_getTransactionByHash"),
+        (_, "/v1/address/:address") => cost("btc2_getLedger"),
+        (_, "/v1/legacy/:txid/:vout") => cost("btc2_getLegacyOutput"),
+        _ => 1,
+    }
+}
+
+// Applied to the whole public router, unmatched paths cost one unit
+pub async fn limit(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Result<Response> {
+    let cost = route_cost(
+        request.method(),
+        request
+            .extensions()
+            .get::<MatchedPath>()
+            .map(MatchedPath::as_str)
+            .unwrap_or_default(),
+    );
+    if cost > 0 {
+        if let Some(client) = Client::new(connect_info, request.headers()) {
+            check(&client, cost)?;
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    // Key into `Limiter::recent`
+    touched: u64,
+}
+
+#[derive(Debug, Default)]
+struct Limiter {
+    buckets: HashMap<Client, Bucket>,
+    // Clients by when they were last seen, least recent first
+    recent: BTreeMap<u64, Client>,
+    touches: u64,
+}
+
+impl Limiter {
+    // Dropping a full bucket changes nothing, the client gets a full one back
+    fn expire(&mut self, now: Instant) {
+        while let Some((_, client)) = self.recent.first_key_value() {
+            if now.duration_since(self.buckets[client].updated_at) < REFILL_TIME {
+                return;
+            }
+            let (_, client) = self.recent.pop_first().unwrap();
+            self.buckets.remove(&client);
+        }
+    }
+
+    // Token buckets that hold a minute's worth of units and refill continuously
+    fn check(&mut self, client: &Client, limit: u64, cost: u64, now: Instant) -> Result<()> {
+        if limit == 0 {
+            return Ok(());
+        }
+        let per_second = limit as f64 / REFILL_TIME.as_secs_f64();
+        self.expire(now);
+        if let Some(bucket) = self.buckets.get(client) {
+            self.recent.remove(&bucket.touched);
+        } else if self.buckets.len() >= MAX_TRACKED_CLIENTS {
+            let (_, oldest) = self.recent.pop_first().unwrap();
+            self.buckets.remove(&oldest);
+        }
+        self.touches += 1;
+        self.recent.insert(self.touches, client.clone());
+        let bucket = self.buckets.entry(client.clone()).or_insert(Bucket {
+            tokens: limit as f64,
+            updated_at: now,
+            touched: self.touches,
+        });
+        bucket.touched = self.touches;
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated_at).as_secs_f64() * per_second)
+            .min(limit as f64);
+        bucket.updated_at = now;
+
+        if bucket.tokens < cost as f64 {
+            let retry_after = (cost as f64 - bucket.tokens) / per_second;
+            return Err(Error::RateLimited(Duration::from_secs_f64(
+                retry_after.ceil(),
+            )));
+        }
+        bucket.tokens -= cost as f64;
+
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref LIMITER: Mutex<Limiter> = Mutex::new(Limiter::default());
+}
+
+static ALLOWED: AtomicU64 = AtomicU64::new(0);
+static LIMITED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Counters {
+    pub allowed: u64,
+    pub limited: u64,
+    pub tracked_clients: u64,
+}
+
+pub fn check(client: &Client, cost: u64) -> Result<()> {
+    let result = LIMITER
+        .lock()
+        .unwrap()
+        .check(client, client.limit(), cost, Instant::now());
+    match &result {
+        Ok(()) => {
+            ALLOWED.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(err) => {
+            LIMITED.fetch_add(1, Ordering::Relaxed);
+            metrics::record_error(err);
+        }
+    }
+
+    result
+}
+
+pub fn counters() -> Counters {
+    Counters {
+        allowed: ALLOWED.load(Ordering::Relaxed),
+        limited: LIMITED.load(Ordering::Relaxed),
+        tracked_clients: LIMITER.lock().unwrap().buckets.len() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        let now = Instant::now();
+        let mut limiter = Limiter::default();
+        let client = Client::Ip(IpAddr::V6(Ipv6Addr::LOCALHOST));
+
+        assert!(limiter.check(&client, 60, 50, now).is_ok());
+        assert!(limiter.check(&client, 60, 10, now).is_ok());
+        assert_eq!(
+            limiter.check(&client, 60, 10, now).unwrap_err().code(),
+            -32005
+        );
+        // One unit a second comes back
+        assert!(limiter
+            .check(&client, 60, 10, now + Duration::from_secs(10))
+            .is_ok());
+        assert!(limiter.check(&client, 0, 1000, now).is_ok());
+    }
+
+    #[test]
+    fn evict() {
+        let now = Instant::now();
+        let mut limiter = Limiter::default();
+        for index in 0..MAX_TRACKED_CLIENTS {
+            let client = Client::ApiKey(index.to_string());
+            assert!(limiter.check(&client, 60, 60, now).is_ok());
+        }
+        // Seen again, so "1" is the least recent now
+        assert!(limiter
+            .check(&Client::ApiKey("0".to_string()), 60, 60, now)
+            .is_err());
+
+        let client = Client::Ip(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert!(limiter.check(&client, 60, 1, now).is_ok());
+        assert_eq!(limiter.buckets.len(), MAX_TRACKED_CLIENTS);
+        assert!(!limiter
+            .buckets
+            .contains_key(&Client::ApiKey("1".to_string())));
+        assert!(limiter
+            .check(&Client::ApiKey("0".to_string()), 60, 60, now)
+            .is_err());
+
+        // Every bucket has refilled by now
+        assert!(limiter.check(&client, 60, 1, now + REFILL_TIME).is_ok());
+        assert_eq!(limiter.buckets.len(), 1);
+        assert_eq!(limiter.recent.len(), 1);
+    }
+
+    #[test]
+    fn network() {
+        let client = |ip: &str| {
+            Client::new(
+                Some(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 443))),
+                &HeaderMap::new(),
+            )
+        };
+
+        assert_eq!(client("2001:db8::1"), client("2001:db8::ffff:1"));
+        assert_ne!(client("2001:db8::1"), client("2001:db8:0:1::1"));
+        assert_eq!(client("::ffff:192.0.2.1"), client("192.0.2.1"));
+        assert_ne!(client("192.0.2.1"), client("192.0.2.2"));
+    }
+}
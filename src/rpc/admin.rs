@@ -0,0 +1,382 @@
+use super::{methods, JsonRpcRequest, ParamValue, ResponseValue};
+use crate::{
+    chain_head::ChainHead,
+    constants::{ADMIN_JWT_SECRET, ADMIN_TOKEN, SNAPSHOT_DIR},
+    control::{self, BlockPolicy},
+    db,
+    error::{Error, Result},
+    snapshot::{self, SnapshotFile},
+    AppState,
+};
+use axum::{
+    body::Bytes,
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use sqlx::{types::time::OffsetDateTime, PgPool};
+use std::{path::Path, time::Duration};
+
+#[derive(Debug, Clone, Default)]
+pub struct AdminCredentials {
+    pub token: Option<String>,
+    // HS256
+    pub jwt_secret: Option<String>,
+}
+
+impl AdminCredentials {
+    pub fn from_env() -> Self {
+        Self {
+            token: ADMIN_TOKEN.clone(),
+            jwt_secret: ADMIN_JWT_SECRET.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.token.is_none() && self.jwt_secret.is_none()
+    }
+
+    fn authorized(&self, headers: &HeaderMap, now: i64) -> bool {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| {
+                self.token
+                    .as_deref()
+                    .is_some_and(|admin_token| Sha256::digest(token) == Sha256::digest(admin_token))
+                    || self
+                        .jwt_secret
+                        .as_deref()
+                        .is_some_and(|secret| verify_jwt(token, secret.as_bytes(), now))
+            })
+    }
+}
+
+// Accepts `Authorization: Bearer <token>` or an HS256 JWT signed with the
+// secret that has an `exp` claim
+pub async fn authorize(
+    State(credentials): State<AdminCredentials>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !credentials.authorized(&headers, OffsetDateTime::now_utc().unix_timestamp()) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(Error::Unauthorized.to_json_rpc(Value::Null)),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+fn verify_jwt(token: &str, secret: &[u8], now: i64) -> bool {
+    let [header, claims, signature] = token.split('.').collect::<Vec<_>>()[..] else {
+        return false;
+    };
+    let decode = |part: &str| -> Option<Value> {
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).ok()?).ok()
+    };
+    let (Some(header_json), Some(claims_json), Ok(signature)) = (
+        decode(header),
+        decode(claims),
+        URL_SAFE_NO_PAD.decode(signature),
+    ) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+    mac.update(format!("{}.{}", header, claims).as_bytes());
+
+    header_json["alg"] == "HS256"
+        && mac.verify_slice(&signature).is_ok()
+        && claims_json["exp"].as_i64().is_some_and(|exp| exp > now)
+}
+
+pub async fn handler(
+    State(state): State<AppState>,
+    body: Bytes,
+) -> axum::response::Result<Response> {
+    let request: JsonRpcRequest =
+        serde_json::from_slice(&body).map_err(|e| Error::InvalidRequest(e.to_string()))?;
+    let id = request.id.clone().unwrap_or_default();
+    let result = match methods::find_admin(&request.method)
+        .and_then(|method| Ok((method, method.params(request.params)?)))
+    {
+        Ok((method, params)) => dispatch(state, method.name, &params).await,
+        Err(err) => Err(err),
+    };
+
+    Ok(Json(match result.and_then(ResponseValue::to_value) {
+        Ok(result) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": result,
+        }),
+        Err(err) => err.to_json_rpc(id),
+    })
+    .into_response())
+}
+
+async fn dispatch(state: AppState, method: &str, params: &[ParamValue]) -> Result<ResponseValue> {
+    let AppState { pool, chain_head } = state;
+
+    Ok(match (method, params) {
+        ("admin_status", []) => status(pool, &chain_head).await?,
+        ("admin_pauseIntake", []) => {
+            control::set_intake_paused(true);
+            status(pool, &chain_head).await?
+        }
+        ("admin_resumeIntake", []) => {
+            control::set_intake_paused(false);
+            status(pool, &chain_head).await?
+        }
+        ("admin_pauseBlockProduction", []) => {
+            control::set_production_paused(true);
+            status(pool, &chain_head).await?
+        }
+        ("admin_resumeBlockProduction", []) => {
+            control::set_production_paused(false);
+            status(pool, &chain_head).await?
+        }
+        ("admin_setBlockPolicy", [policy]) => {
+            set_block_policy(&policy.0)?;
+            status(pool, &chain_head).await?
+        }
+        ("admin_dropTransaction", [transaction_hash]) => ResponseValue::Value(json!(
+            db::drop_pending_transaction(&pool, transaction_hash.try_into()?).await?
+        )),
+        ("admin_exportSnapshot", []) => export_snapshot(pool).await?,
+        _ => return Err(Error::UnsupportedMethod(method.to_string())),
+    })
+}
+
+async fn status(pool: PgPool, chain_head: &ChainHead) -> Result<ResponseValue> {
+    let block_policy = control::block_policy();
+    let pending_transactions = db::get_pending_transaction_count(&pool).await?;
+
+    Ok(ResponseValue::Value(json!({
+        "intakePaused": control::intake_paused(),
+        "blockProductionPaused": control::production_paused(),
+        "blockPolicy": {
+            "blockTime": block_policy.block_time.as_millis() as u64,
+            "maxTransactions": block_policy.max_transactions,
+        },
+        "blockNumber": chain_head.block_number(),
+        "pendingTransactions": pending_transactions,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BlockPolicyParam {
+    // Milliseconds, unchanged when missing
+    block_time: Option<u64>,
+    // Unchanged when missing, unlimited when null
+    #[serde(default, deserialize_with = "deserialize_some")]
+    max_transactions: Option<Option<usize>>,
+}
+
+// Distinguishes an explicit null from a missing field
+fn deserialize_some<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(Some(T::deserialize(deserializer)?))
+}
+
+fn set_block_policy(policy: &Value) -> Result<()> {
+    let policy: BlockPolicyParam =
+        serde_json::from_value(policy.clone()).map_err(|e| Error::ParseError(e.to_string()))?;
+    if policy.block_time == Some(0) || policy.max_transactions == Some(Some(0)) {
+        return Err(Error::ParseError(
+            "blockTime and maxTransactions must be positive".to_string(),
+        ));
+    }
+
+    let current = control::block_policy();
+    control::set_block_policy(BlockPolicy {
+        block_time: policy
+            .block_time
+            .map(Duration::from_millis)
+            .unwrap_or(current.block_time),
+        max_transactions: policy.max_transactions.unwrap_or(current.max_transactions),
+    });
+
+    Ok(())
+}
+
+// Written to SNAPSHOT_DIR rather than a caller supplied path
+async fn export_snapshot(pool: PgPool) -> Result<ResponseValue> {
+    let file = SnapshotFile::new(snapshot::export(&pool, None).await?)?;
+    std::fs::create_dir_all(SNAPSHOT_DIR.as_str())?;
+    let path =
+        Path::new(SNAPSHOT_DIR.as_str()).join(format!("snapshot-{}.json", file.snapshot.height));
+    file.write(&path)?;
+
+    Ok(ResponseValue::Value(json!({
+        "height": file.snapshot.height,
+        "checksum": file.checksum,
+        "path": path.to_string_lossy(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin_router;
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    fn jwt(secret: &[u8], alg: &str, exp: i64) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({ "alg": alg, "typ": "JWT" }).to_string());
+        let claims = URL_SAFE_NO_PAD.encode(json!({ "exp": exp }).to_string());
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(format!("{}.{}", header, claims).as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+
+        format!("{}.{}.{}", header, claims, signature)
+    }
+
+    #[test]
+    fn verify() {
+        let now = 1_700_000_000;
+
+        assert!(verify_jwt(
+            &jwt(b"secret", "HS256", now + 60),
+            b"secret",
+            now
+        ));
+        assert!(!verify_jwt(
+            &jwt(b"secret", "HS256", now - 60),
+            b"secret",
+            now
+        ));
+        assert!(!verify_jwt(
+            &jwt(b"other", "HS256", now + 60),
+            b"secret",
+            now
+        ));
+        assert!(!verify_jwt(
+            &jwt(b"secret", "none", now + 60),
+            b"secret",
+            now
+        ));
+        assert!(!verify_jwt("secret", b"secret", now));
+    }
+
+    #[test]
+    fn authorized() {
+        let now = 1_700_000_000;
+        let credentials = AdminCredentials {
+            token: Some("admin-token".to_string()),
+            jwt_secret: Some("secret".to_string()),
+        };
+        let headers = |authorization: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+            headers
+        };
+
+        assert!(credentials.authorized(&headers("Bearer admin-token"), now));
+        assert!(credentials.authorized(
+            &headers(&format!("Bearer {}", jwt(b"secret", "HS256", now + 60))),
+            now
+        ));
+        assert!(!credentials.authorized(&headers("Bearer wrong"), now));
+        assert!(!credentials.authorized(&headers("admin-token"), now));
+        assert!(!credentials.authorized(&HeaderMap::new(), now));
+        assert!(!AdminCredentials::default().authorized(&headers("Bearer admin-token"), now));
+    }
+
+    #[sqlx::test]
+    async fn admin_router_authorizes(pool: PgPool) -> sqlx::Result<()> {
+        let token = "admin-token";
+        let app = admin_router(
+            AppState::load(pool.clone()).await.unwrap(),
+            AdminCredentials {
+                token: Some(token.to_string()),
+                jwt_secret: None,
+            },
+        );
+        let request = |authorization: Option<String>| {
+            let mut request = Request::builder()
+                .method("POST")
+                .header(header::CONTENT_TYPE, "application/json")
+                .uri("/");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            request
+                .body(Body::from(
+                    json!({"jsonrpc": "2.0", "method": "admin_status", "id": 1}).to_string(),
+                ))
+                .unwrap()
+        };
+
+        for authorization in [None, Some("Bearer wrong".to_string())] {
+            let response = app.clone().oneshot(request(authorization)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(request(Some(format!("Bearer {}", token))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["result"]["pendingTransactions"], 0);
+
+        // Rejected before any method runs, but still counted
+        let public = crate::app(pool).await;
+        let response = public
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .uri("/")
+                    .body(Body::from(
+                        json!({"jsonrpc": "2.0", "method": "eth_mine", "id": 1}).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let metrics = |authorization: Option<String>| {
+            let mut request = Request::builder().uri("/metrics");
+            if let Some(authorization) = authorization {
+                request = request.header(header::AUTHORIZATION, authorization);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+        let response = public.oneshot(metrics(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = app.clone().oneshot(metrics(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = app
+            .oneshot(metrics(Some(format!("Bearer {}", token))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("bitcoin2_rpc_errors_total{error=\"UnsupportedMethod\"}"));
+
+        Ok(())
+    }
+}
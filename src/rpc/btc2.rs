@@ -1,25 +1,402 @@
-use super::{encode_bytes, ResponseValue};
-use crate::db;
-use axum::response::Result;
+use super::{encode_bytes, ParamValue, ResponseValue};
+use crate::{
+    bitcoin_legacy,
+    bitcoin_legacy::utxos,
+    constants::{LEGACY_ACCOUNT, LEGACY_SUPPLY, SYSTEM_ADDRESS},
+    db,
+    db::{LedgerDirection, LedgerEntry, LedgerHistoryEntry, LedgerQuery},
+    error::{Error, Result},
+    evm::upgrade_by_message::{Outpoint, UpgradeByMessage},
+};
 
-use crate::{evm::scale_up, rpc::encode_u256};
+use crate::{
+    evm::scale_up,
+    rpc::{encode_amount, encode_u256},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
 use serde_json::{json, Value};
+use std::collections::HashSet;
+use tokio::task::spawn_blocking;
 
 use sqlx::PgPool;
 
-pub async fn get_transactions(pool: PgPool, address: [u8; 20]) -> Result<ResponseValue> {
-    let entries = db::get_ledger_by_address(&pool, address).await?;
-    Ok(ResponseValue::Value(serde_json::Value::Array(
-        entries
+const MAX_RAW_BLOCKS: i64 = 100;
+pub const DEFAULT_LEDGER_PAGE_SIZE: i64 = 100;
+pub const MAX_LEDGER_PAGE_SIZE: i64 = 1000;
+const UPGRADE_SIGNATURE_LENGTH: usize = 65;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Order {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct LedgerOptions {
+    cursor: Option<Value>,
+    limit: Option<Value>,
+    from_block: Option<Value>,
+    to_block: Option<Value>,
+    direction: Option<LedgerDirection>,
+    #[serde(default)]
+    order: Order,
+}
+
+fn quantity(value: Option<Value>) -> Result<Option<i64>> {
+    value
+        .map(|value| i64::try_from(&ParamValue(value)))
+        .transpose()
+}
+
+// Without options the newest MAX_LEDGER_PAGE_SIZE entries are returned as an
+// array, oldest first, the shape from before pagination. With any option it's
+// a page of the newest entries by default, pass `nextCursor` back as `cursor`
+// to get the next page.
+pub async fn get_ledger(pool: PgPool, address: [u8; 20], options: &Value) -> Result<ResponseValue> {
+    if *options == json!({}) {
+        let entries = db::get_ledger_history(
+            &pool,
+            &LedgerQuery {
+                address,
+                cursor: None,
+                limit: Some(MAX_LEDGER_PAGE_SIZE),
+                from_block: None,
+                to_block: None,
+                direction: None,
+                descending: true,
+            },
+        )
+        .await?;
+
+        return Ok(ResponseValue::Value(Value::Array(
+            entries
+                .iter()
+                .rev()
+                .map(|entry| ledger_entry(address, entry))
+                .collect(),
+        )));
+    }
+    let options: LedgerOptions =
+        serde_json::from_value(options.clone()).map_err(|e| Error::ParseError(e.to_string()))?;
+    let limit = quantity(options.limit)?
+        .unwrap_or(DEFAULT_LEDGER_PAGE_SIZE)
+        .clamp(1, MAX_LEDGER_PAGE_SIZE);
+    let entries = db::get_ledger_history(
+        &pool,
+        &LedgerQuery {
+            address,
+            cursor: quantity(options.cursor)?,
+            limit: Some(limit),
+            from_block: quantity(options.from_block)?,
+            to_block: quantity(options.to_block)?,
+            direction: options.direction,
+            descending: matches!(options.order, Order::Desc),
+        },
+    )
+    .await?;
+    let next_cursor = match entries.last() {
+        Some(entry) if entries.len() as i64 == limit => {
+            Some(encode_amount((entry.id as u64).into()))
+        }
+        _ => None,
+    };
+
+    Ok(ResponseValue::Value(json!({
+        "entries": entries
+            .iter()
+            .map(|entry| ledger_entry(address, entry))
+            .collect::<Vec<Value>>(),
+        "nextCursor": next_cursor,
+    })))
+}
+
+pub fn ledger_entry(address: [u8; 20], entry: &LedgerHistoryEntry) -> Value {
+    let LedgerEntry {
+        creditor,
+        debtor,
+        value,
+    } = entry.entry;
+    let direction = match (creditor == address, debtor == address) {
+        (true, true) => "self",
+        (true, false) => "out",
+        _ => "in",
+    };
+
+    json!({
+        "id": encode_amount((entry.id as u64).into()),
+        "transactionHash": encode_bytes(&entry.transaction_hash),
+        "blockNumber": entry.block_number.map(|number| encode_amount((number as u64).into())),
+        "timestamp": entry.timestamp.map(|timestamp| {
+            encode_amount((timestamp.assume_utc().unix_timestamp() as u64).into())
+        }),
+        "type": entry.entry_type.name(),
+        "direction": direction,
+        "creditor": encode_bytes(&creditor),
+        "debtor": encode_bytes(&debtor),
+        "value": encode_u256(scale_up(value)),
+    })
+}
+
+// Txids are given in display order, with or without a 0x prefix
+pub fn parse_txid(txid: &str) -> Result<[u8; 32]> {
+    hex::decode(txid.trim_start_matches("0x"))
+        .map_err(|e| Error::ParseError(e.to_string()))?
+        .try_into()
+        .map_err(|_| Error::ParseError("Expected 32 byte txid".to_string()))
+}
+
+pub async fn get_legacy_output(pool: PgPool, txid: [u8; 32], vout: i64) -> Result<ResponseValue> {
+    let index: u16 = vout
+        .try_into()
+        .map_err(|_| Error::ParseError("Invalid vout".to_string()))?;
+    let Some(output) = spawn_blocking(move || {
+        utxos::get(&utxos::Outpoint {
+            hash: reversed(txid),
+            index,
+        })
+    })
+    .await??
+    else {
+        return Ok(ResponseValue::Null);
+    };
+    let spent = db::get_spent_legacy_output(&pool, txid, i16::try_from(index)?).await?;
+
+    Ok(ResponseValue::Value(json!({
+        "txid": hex::encode(txid),
+        "vout": index,
+        "amount": encode_amount((output.amount as u64).into()),
+        "scriptType": output.script_type.name(),
+        "claimable": output.script_type.claimable(),
+        "upgraded": spent.is_some(),
+        "upgradeTransactionHash": spent
+            .and_then(|spent| spent.transaction_hash)
+            .map(|hash| encode_bytes(&hash)),
+    })))
+}
+
+// Unclaimed snapshot outputs locked to a mainnet Bitcoin address
+pub async fn get_legacy_balance(pool: PgPool, address: String) -> Result<ResponseValue> {
+    let script_pubkey = bitcoin_legacy::address::script_pubkey(&address)?;
+    let script_type = utxos::classify(&script_pubkey);
+    let outputs: Vec<([u8; 32], u16, i64)> =
+        spawn_blocking(move || utxos::get_by_script_pubkey(&script_pubkey))
+            .await??
             .into_iter()
-            .map(|entry| {
-                json!(
-                {
-                    "creditor": encode_bytes(&entry.creditor),
-                    "debtor": encode_bytes(&entry.debtor),
-                    "value": encode_u256(scale_up(entry.value)),
+            .map(|(outpoint, amount)| (reversed(outpoint.hash), outpoint.index, amount))
+            .collect();
+    let outpoints = outputs
+        .iter()
+        .map(|(txid, index, _)| Ok((*txid, i16::try_from(*index)?)))
+        .collect::<Result<Vec<([u8; 32], i16)>>>()?;
+    let spent: HashSet<([u8; 32], i16)> = db::get_spent_legacy_outputs_in(&pool, &outpoints)
+        .await?
+        .into_iter()
+        .map(|spent| (spent.hash, spent.index))
+        .collect();
+    let unclaimed: Vec<([u8; 32], u16, i64)> = outputs
+        .into_iter()
+        .zip(&outpoints)
+        .filter(|(_, outpoint)| !spent.contains(outpoint))
+        .map(|(output, _)| output)
+        .collect();
+    let total: i64 = unclaimed.iter().map(|(_, _, amount)| amount).sum();
+
+    Ok(ResponseValue::Value(json!({
+        "address": address,
+        "scriptType": script_type.name(),
+        "claimable": script_type.claimable(),
+        "outputs": unclaimed
+            .iter()
+            .map(|(txid, index, amount)| {
+                json!({
+                    "txid": hex::encode(txid),
+                    "vout": index,
+                    "amount": encode_amount((*amount as u64).into()),
                 })
             })
             .collect::<Vec<Value>>(),
+        "total": encode_amount((total as u64).into()),
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutpointParam {
+    txid: String,
+    vout: u16,
+}
+
+// Sign `message` with the key behind the outpoints, then splice the 65 byte
+// signature into `data` at `signatureOffset` and send it to the system address
+pub async fn build_upgrade_message(
+    pool: PgPool,
+    destination: [u8; 20],
+    outpoints: &Value,
+) -> Result<ResponseValue> {
+    let outpoints: Vec<OutpointParam> =
+        serde_json::from_value(outpoints.clone()).map_err(|e| Error::ParseError(e.to_string()))?;
+    if outpoints.is_empty() {
+        return Err(Error::ParseError(
+            "Expected at least one outpoint".to_string(),
+        ));
+    }
+
+    let mut inputs: Vec<Outpoint> = vec![];
+    for outpoint in &outpoints {
+        let input = Outpoint {
+            hash: parse_txid(&outpoint.txid)?,
+            index: i16::try_from(outpoint.vout)?,
+        };
+        if inputs.contains(&input) {
+            return Err(Error::ParseError(format!(
+                "{}:{} is listed more than once",
+                hex::encode(input.hash),
+                outpoint.vout
+            )));
+        }
+        inputs.push(input);
+    }
+
+    let legacy_outpoints = outpoints
+        .iter()
+        .zip(&inputs)
+        .map(|(outpoint, input)| utxos::Outpoint {
+            hash: reversed(input.hash),
+            index: outpoint.vout,
+        })
+        .collect::<Vec<_>>();
+    let outputs = spawn_blocking(move || utxos::get_many(&legacy_outpoints)).await??;
+
+    let mut amount = 0;
+    for ((input, outpoint), output) in inputs.iter().zip(&outpoints).zip(outputs) {
+        let output = output.ok_or(Error::OutpointNotFound)?;
+        if !output.script_type.claimable() {
+            return Err(Error::Error(format!(
+                "{}:{} is a {} output, which can't be upgraded",
+                hex::encode(input.hash),
+                outpoint.vout,
+                output.script_type.name()
+            )));
+        }
+        if db::get_spent_legacy_output(&pool, input.hash, input.index)
+            .await?
+            .is_some()
+        {
+            return Err(Error::Error(format!(
+                "{}:{} has already been upgraded",
+                hex::encode(input.hash),
+                outpoint.vout
+            )));
+        }
+        amount += output.amount;
+    }
+    let upgrade_by_message = UpgradeByMessage::new(destination, inputs);
+    let data = upgrade_by_message.calldata(&[0; UPGRADE_SIGNATURE_LENGTH]);
+
+    Ok(ResponseValue::Value(json!({
+        "message": upgrade_by_message.to_message(),
+        "amount": encode_amount((amount as u64).into()),
+        "to": encode_bytes(&SYSTEM_ADDRESS),
+        "data": encode_bytes(&data),
+        // The signature is the last dynamic value, padded to a 32 byte boundary
+        "signatureOffset": data.len() - UPGRADE_SIGNATURE_LENGTH.next_multiple_of(32),
+    })))
+}
+
+// Amounts are in satoshis
+pub async fn get_supply(pool: PgPool) -> Result<ResponseValue> {
+    let unclaimed = db::get_balance(&pool, LEGACY_ACCOUNT).await?;
+
+    Ok(ResponseValue::Value(json!({
+        "legacySupply": encode_amount((LEGACY_SUPPLY as u64).into()),
+        "legacyUnclaimed": encode_amount((unclaimed as u64).into()),
+        "upgraded": encode_amount(((LEGACY_SUPPLY - unclaimed) as u64).into()),
+    })))
+}
+
+pub async fn get_upgrade_stats(pool: PgPool) -> Result<ResponseValue> {
+    let totals = db::get_upgrade_totals(&pool).await?;
+    let series = db::get_upgrade_series(&pool).await?;
+    let by_script_type = db::get_upgrade_totals_by_script_type(&pool).await?;
+
+    Ok(ResponseValue::Value(json!({
+        "upgraded": encode_amount((totals.upgraded as u64).into()),
+        "outpoints": encode_amount((totals.outpoints as u64).into()),
+        "recipients": encode_amount((totals.recipients as u64).into()),
+        "byScriptType": by_script_type
+            .into_iter()
+            .map(|(script_type, outpoints, amount)| {
+                (
+                    script_type,
+                    json!({
+                        "outpoints": encode_amount((outpoints as u64).into()),
+                        "amount": encode_amount((amount as u64).into()),
+                    }),
+                )
+            })
+            .collect::<serde_json::Map<String, Value>>(),
+        "series": series
+            .into_iter()
+            .map(|(block_number, totals)| {
+                json!({
+                    "blockNumber": encode_amount((block_number as u64).into()),
+                    "upgraded": encode_amount((totals.upgraded as u64).into()),
+                    "outpoints": encode_amount((totals.outpoints as u64).into()),
+                    "recipients": encode_amount((totals.recipients as u64).into()),
+                })
+            })
+            .collect::<Vec<Value>>(),
+    })))
+}
+
+pub fn reversed(hash: [u8; 32]) -> [u8; 32] {
+    let mut hash = hash;
+    hash.reverse();
+    hash
+}
+
+// Everything a follower needs to re-verify and apply the blocks after `block_number`
+pub async fn get_raw_blocks(pool: PgPool, block_number: i64, count: i64) -> Result<ResponseValue> {
+    let mut raw_blocks = vec![];
+    for block in
+        db::get_blocks_after(&pool, block_number, Some(count.clamp(0, MAX_RAW_BLOCKS))).await?
+    {
+        let transactions = db::get_transactions_by_block_number(&pool, Some(block.number)).await?;
+        raw_blocks.push(json!({
+            "number": encode_amount((block.number as u64).into()),
+            "hash": encode_bytes(&block.hash),
+            "timestamp": encode_amount((block.timestamp.assume_utc().unix_timestamp() as u64).into()),
+            "signature": block.signature.map(|signature| encode_bytes(&signature)),
+            "transactions": transactions
+                .into_iter()
+                .map(|transaction| encode_bytes(&transaction.1.envelope_encoded()))
+                .collect::<Vec<Value>>(),
+        }));
+    }
+
+    Ok(ResponseValue::Value(Value::Array(raw_blocks)))
+}
+
+pub async fn get_anchors(pool: PgPool) -> Result<ResponseValue> {
+    Ok(ResponseValue::Value(Value::Array(
+        db::get_anchors(&pool, false)
+            .await?
+            .into_iter()
+            .map(|anchor| {
+                json!({
+                    "blockNumber": encode_amount((anchor.block_number as u64).into()),
+                    "blockHash": encode_bytes(&anchor.block_hash),
+                    "txid": encode_bytes(&anchor.txid),
+                    "psbt": STANDARD.encode(&anchor.psbt),
+                    "status": if anchor.bitcoin_block_hash.is_some() { "confirmed" } else { "pending" },
+                    "bitcoinBlockHash": anchor.bitcoin_block_hash.map(|hash| encode_bytes(&hash)),
+                    "bitcoinBlockHeight": anchor.bitcoin_block_height,
+                })
+            })
+            .collect(),
     )))
 }
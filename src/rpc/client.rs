@@ -0,0 +1,34 @@
+use crate::error::{Error, Result};
+use serde_json::{json, Value};
+
+// Sends a request as-is and returns the whole response object
+pub async fn forward(url: &str, request: &Value) -> Result<Value> {
+    Ok(reqwest::Client::new()
+        .post(url)
+        .json(request)
+        .send()
+        .await?
+        .json()
+        .await?)
+}
+
+pub async fn call(url: &str, method: &str, params: Value) -> Result<Value> {
+    let mut response = forward(
+        url,
+        &json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        }),
+    )
+    .await?;
+
+    if let Some(error) = response.get("error") {
+        return Err(Error::Error(format!("{} failed: {}", method, error)));
+    }
+    Ok(response
+        .get_mut("result")
+        .map(Value::take)
+        .unwrap_or(Value::Null))
+}
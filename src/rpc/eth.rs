@@ -1,13 +1,15 @@
-use super::ResponseValue;
+use super::{parse_bytes_like, ResponseValue};
 use crate::{
-    constants::{CHAIN_ID, DEFAULT_GAS_LIMIT},
-    db,
-    db::TransactionSignedRow,
-    error::Error,
+    chain_head::ChainHead,
+    constants::CHAIN_ID,
+    control, db,
+    db::{Block, StoredTransaction},
+    error::{Error, Result},
+    events::{self, Event},
     evm,
     evm::{scale_up, Evm},
+    metrics,
 };
-use axum::response::Result;
 use crate::rpc::BlockTag;
 use num_bigint::BigUint;
 use num_traits::Zero;
@@ -15,10 +17,32 @@ use reth_primitives::U256;
 use serde_json::{json, Value};
 use sqlx::PgPool;
 
+const MAX_FEE_HISTORY_BLOCKS: i64 = 1024;
+// keccak256(rlp([]))
+const EMPTY_UNCLES_HASH: [u8; 32] =
+    hex_lit::hex!("1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347");
+
 pub async fn chain_id() -> Result<ResponseValue> {
     Ok(ResponseValue::Number(U256::from::<i64>(CHAIN_ID)))
 }
 pub async fn send_raw_transaction(pool: PgPool, raw_transaction: Vec<u8>) -> Result<ResponseValue> {
+    let result = submit_transaction(pool, raw_transaction).await;
+    metrics::record_transaction(result.is_ok());
+    let transaction = result?;
+    events::publish(Event::PendingTransaction(transaction.hash().into()));
+
+    Ok(ResponseValue::Value(encode_bytes(
+        &transaction.hash().to_vec(),
+    )))
+}
+
+async fn submit_transaction(
+    pool: PgPool,
+    raw_transaction: Vec<u8>,
+) -> Result<evm::TransactionSigned> {
+    if control::intake_paused() {
+        return Err(Error::IntakePaused);
+    }
     let transaction =
         evm::TransactionSigned::decode_rlp_legacy_transaction(&mut &raw_transaction[..])
             .map_err(Error::from)?;
@@ -26,9 +50,7 @@ pub async fn send_raw_transaction(pool: PgPool, raw_transaction: Vec<u8>) -> Res
     let evm: Evm = Evm::new(pool);
     evm.run_transaction(&transaction).await?;
 
-    Ok(ResponseValue::Value(encode_bytes(
-        &transaction.hash().to_vec(),
-    )))
+    Ok(transaction)
 }
 
 pub fn encode_u256(amount: U256) -> Value {
@@ -57,118 +79,292 @@ pub fn encode_bytes(bytes: &[u8]) -> Value {
     json!(format!("0x{}", hex::encode(bytes)))
 }
 
-pub async fn get_transaction_by_hash(transaction_hash: [u8; 32]) -> Result<ResponseValue> {
-    Ok(ResponseValue::Value(json!({
-      "blockHash": encode_bytes(&transaction_hash),
-      "hash": encode_bytes(&transaction_hash),
-      "blockNumber":"0x1",
-      "cumulativeGasUsed": "0x0",
-      "transactionIndex": "0x0",
-      "effectiveGasPrice": "0x0",
-      "from": encode_bytes(&[0; 20]),
-      "transactionHash": encode_bytes(&transaction_hash),
-      "status":"0x1",
-      "logs": [],
-      "gasUsed":"0x0",
-      "gasLimit": "0x0",
-      "value": "0x0",
-      "input": "0x",
-      "nonce": "0x0",
-      "v": "0x0",
-      "r": encode_bytes(&[0; 32]),
-      "s": encode_bytes(&[0; 32]),
-    })))
-}
-
-pub async fn get_transaction_receipt(transaction_hash: [u8; 32]) -> Result<ResponseValue> {
-    Ok(ResponseValue::Value(json!({
-      "blockHash":encode_bytes(&transaction_hash),
-      "blockNumber":"0x1",
-      "cumulativeGasUsed": "0x0",
-      "transactionIndex": "0x0",
-      "effectiveGasPrice": "0x0",
-      "gasLimit": "0x0",
-      "transactionHash": encode_bytes(&transaction_hash),
-      "status":"0x1",
-      "logs": [],
-      "gasUsed":"0x0",
-    })))
+pub async fn get_transaction_by_hash(
+    pool: PgPool,
+    transaction_hash: [u8; 32],
+) -> Result<ResponseValue> {
+    Ok(
+        match db::get_stored_transaction_by_hash(&pool, transaction_hash).await? {
+            Some(stored) => ResponseValue::Value(transaction_object(&stored)),
+            None => ResponseValue::Null,
+        },
+    )
+}
+
+// Failed transactions are rejected instead of stored so every receipt is
+// successful, and transfers and upgrades only ever use their intrinsic gas
+pub async fn get_transaction_receipt(
+    pool: PgPool,
+    transaction_hash: [u8; 32],
+) -> Result<ResponseValue> {
+    let Some(stored) = db::get_stored_transaction_by_hash(&pool, transaction_hash).await? else {
+        return Ok(ResponseValue::Null);
+    };
+    let Some(block_number) = stored.block_number else {
+        return Ok(ResponseValue::Null);
+    };
+    let cumulative_gas_used = db::get_stored_transactions_by_block_number(&pool, block_number)
+        .await?
+        .iter()
+        .take(stored.transaction_index as usize + 1)
+        .map(|stored| evm::intrinsic_gas(stored.transaction.input()))
+        .sum();
+
+    Ok(receipt_object(&stored, cumulative_gas_used).into())
+}
+
+// `None` until the transaction is included in a block
+pub fn receipt_object(stored: &StoredTransaction, cumulative_gas_used: u64) -> Option<Value> {
+    let (block_number, block_hash) = (stored.block_number?, stored.block_hash?);
+    let transaction = &stored.transaction;
+    let transaction_index = stored.transaction_index as u64;
+    let gas_used = evm::intrinsic_gas(transaction.input());
+
+    Some(json!({
+        "transactionHash": encode_bytes(&stored.hash),
+        "transactionIndex": encode_amount(transaction_index.into()),
+        "blockHash": encode_bytes(&block_hash),
+        "blockNumber": encode_amount((block_number as u64).into()),
+        "from": encode_bytes(&stored.signer),
+        "to": transaction.to().map(|to| encode_bytes(to.as_slice())),
+        "cumulativeGasUsed": encode_amount(cumulative_gas_used.into()),
+        "gasUsed": encode_amount(gas_used.into()),
+        "effectiveGasPrice": encode_amount(transaction.max_fee_per_gas().into()),
+        "contractAddress": null,
+        "logs": [],
+        "logsBloom": encode_bytes(&[0; 256]),
+        "type": "0x0",
+        "status": "0x1",
+    }))
+}
+
+pub fn transaction_object(stored: &StoredTransaction) -> Value {
+    let transaction = &stored.transaction;
+    let transaction_index = stored
+        .block_number
+        .map(|_| encode_amount((stored.transaction_index as u64).into()));
+
+    json!({
+        "hash": encode_bytes(&stored.hash),
+        "blockHash": stored.block_hash.map(|hash| encode_bytes(&hash)),
+        "blockNumber": stored.block_number.map(|number| encode_amount((number as u64).into())),
+        "transactionIndex": transaction_index,
+        "from": encode_bytes(&stored.signer),
+        "to": transaction.to().map(|to| encode_bytes(to.as_slice())),
+        "nonce": encode_amount(transaction.nonce().into()),
+        "gas": encode_amount(transaction.gas_limit().into()),
+        "gasPrice": encode_amount(transaction.max_fee_per_gas().into()),
+        "value": encode_u256(transaction.value()),
+        "input": encode_bytes(transaction.input()),
+        "type": "0x0",
+        "chainId": encode_amount((CHAIN_ID as u64).into()),
+        "v": encode_amount(transaction.signature.v(transaction.chain_id()).into()),
+        "r": encode_u256(transaction.signature.r),
+        "s": encode_u256(transaction.signature.s),
+    })
 }
+
 pub async fn get_transaction_count(pool: PgPool, address: [u8; 20]) -> Result<ResponseValue> {
     Ok(ResponseValue::Number(U256::from::<i64>(
         db::get_transaction_count_by_address(&pool, address).await?,
     )))
 }
-pub async fn get_code(_block_hash: [u8; 20]) -> Result<ResponseValue> {
+pub async fn get_code(_address: [u8; 20]) -> Result<ResponseValue> {
     Ok(ResponseValue::Value(json!("0x")))
 }
-pub async fn get_block_by_number(pool: PgPool, block_tag: BlockTag) -> Result<ResponseValue> {
-    let block_number = if let BlockTag::Number(block_number) = block_tag {
-        block_number
-    } else {
-        db::get_transaction_count(&pool).await.unwrap_or(0)
-    };
-    if let Ok(TransactionSignedRow(_, transaction)) =
-        db::get_transaction_by_id(&pool, block_number).await
-    {
-        Ok(ResponseValue::Value(json!({
-           "hash": encode_bytes(&transaction.hash().to_vec()),
-            "parentHash":encode_bytes(&[0; 32].to_vec()),
-            "number": encode_amount(0u32.into()),
-            "miner": encode_bytes(&[0; 20].to_vec()),
-            "extraData": encode_bytes(&vec![]),
-            "gasLimit": encode_amount(0u32.into()),
-            "gasUsed": encode_amount(0u32.into()),
-            "timestamp": encode_amount(0u32.into()),
-            "difficulty": encode_amount(0u32.into()),
-            "transactions": vec![encode_bytes(&transaction.hash().to_vec())],
-        })))
-    } else {
-        let transactions: Vec<()> = vec![];
-        Ok(ResponseValue::Value(json!({
-           "hash": encode_bytes(&[0; 32]),
-            "parentHash":encode_bytes(&[0; 32].to_vec()),
-            "number": encode_amount(0u32.into()),
-            "miner": encode_bytes(&[0; 20].to_vec()),
-            "extraData": encode_bytes(&vec![]),
-            "gasLimit": encode_amount(0u32.into()),
-            "gasUsed": encode_amount(0u32.into()),
-            "timestamp": encode_amount(0u32.into()),
-            "transactions": transactions,
-        })))
-    }
+pub async fn get_block_by_number(
+    pool: PgPool,
+    chain_head: &ChainHead,
+    block_tag: BlockTag,
+    include_transactions: bool,
+) -> Result<ResponseValue> {
+    let block = find_block_by_tag(&pool, chain_head, block_tag).await?;
+
+    block_response(&pool, block, include_transactions).await
 }
-pub async fn get_block_by_hash(pool: PgPool, block_hash: [u8; 32]) -> Result<ResponseValue> {
-    if let Ok(TransactionSignedRow(_, transaction)) =
-        db::get_transaction_by_hash(&pool, block_hash).await
-    {
-        Ok(ResponseValue::Value(json!({
-           "hash": encode_bytes(&transaction.hash().to_vec()),
-            "parentHash":encode_bytes(&[0; 32].to_vec()),
-            "number": encode_amount(0u32.into()),
-            "miner": encode_bytes(&[0; 32].to_vec()),
-            "extraData": encode_bytes(&vec![]),
-            "gasLimit": encode_amount(0u32.into()),
-            "gasUsed": encode_amount(0u32.into()),
-            "timestamp": encode_amount(0u32.into()),
-            "transactions": vec![encode_bytes(&transaction.hash().to_vec())],
-        })))
-    } else {
-        let transactions: Vec<()> = vec![];
-        Ok(ResponseValue::Value(json!({
-           "hash": encode_bytes(&[0; 32]),
-            "parentHash":encode_bytes(&[0; 32].to_vec()),
-            "number": encode_amount(0u32.into()),
-            "miner": encode_bytes(&[0; 32].to_vec()),
-            "extraData": encode_bytes(&vec![]),
-            "gasLimit": encode_amount(0u32.into()),
-            "gasUsed": encode_amount(0u32.into()),
-            "timestamp": encode_amount(0u32.into()),
-            "transactions": transactions,
-        })))
+
+pub async fn get_block_by_hash(
+    pool: PgPool,
+    block_hash: [u8; 32],
+    include_transactions: bool,
+) -> Result<ResponseValue> {
+    let block = db::get_block_by_hash(&pool, block_hash).await?;
+
+    block_response(&pool, block, include_transactions).await
+}
+
+pub async fn get_block_transaction_count_by_number(
+    pool: PgPool,
+    chain_head: &ChainHead,
+    block_tag: BlockTag,
+) -> Result<ResponseValue> {
+    let block = find_block_by_tag(&pool, chain_head, block_tag).await?;
+
+    block_transaction_count(&pool, block).await
+}
+
+pub async fn get_block_transaction_count_by_hash(
+    pool: PgPool,
+    block_hash: [u8; 32],
+) -> Result<ResponseValue> {
+    let block = db::get_block_by_hash(&pool, block_hash).await?;
+
+    block_transaction_count(&pool, block).await
+}
+
+pub async fn get_transaction_by_block_number_and_index(
+    pool: PgPool,
+    chain_head: &ChainHead,
+    block_tag: BlockTag,
+    index: i64,
+) -> Result<ResponseValue> {
+    let block = find_block_by_tag(&pool, chain_head, block_tag).await?;
+
+    transaction_by_index(&pool, block, index).await
+}
+
+pub async fn get_transaction_by_block_hash_and_index(
+    pool: PgPool,
+    block_hash: [u8; 32],
+    index: i64,
+) -> Result<ResponseValue> {
+    let block = db::get_block_by_hash(&pool, block_hash).await?;
+
+    transaction_by_index(&pool, block, index).await
+}
+
+pub async fn get_block_receipts(
+    pool: PgPool,
+    chain_head: &ChainHead,
+    block_tag: BlockTag,
+) -> Result<ResponseValue> {
+    let block = find_block_by_tag(&pool, chain_head, block_tag).await?;
+
+    Ok(block_transactions(&pool, block)
+        .await?
+        .map(|transactions| {
+            let mut cumulative_gas_used = 0;
+            transactions
+                .iter()
+                .filter_map(|stored| {
+                    cumulative_gas_used += evm::intrinsic_gas(stored.transaction.input());
+                    receipt_object(stored, cumulative_gas_used)
+                })
+                .collect()
+        })
+        .into())
+}
+
+async fn block_transaction_count(pool: &PgPool, block: Option<Block>) -> Result<ResponseValue> {
+    Ok(block_transactions(pool, block)
+        .await?
+        .map(|transactions| encode_amount(transactions.len().into()))
+        .into())
+}
+
+async fn transaction_by_index(
+    pool: &PgPool,
+    block: Option<Block>,
+    index: i64,
+) -> Result<ResponseValue> {
+    Ok(block_transactions(pool, block)
+        .await?
+        .and_then(|transactions| {
+            transactions
+                .get(usize::try_from(index).ok()?)
+                .map(transaction_object)
+        })
+        .into())
+}
+
+async fn block_transactions(
+    pool: &PgPool,
+    block: Option<Block>,
+) -> Result<Option<Vec<StoredTransaction>>> {
+    Ok(match block {
+        Some(block) => Some(db::get_stored_transactions_by_block_number(pool, block.number).await?),
+        None => None,
+    })
+}
+
+async fn find_block_by_tag(
+    pool: &PgPool,
+    chain_head: &ChainHead,
+    block_tag: BlockTag,
+) -> Result<Option<Block>> {
+    match resolve_block_tag(pool, chain_head, block_tag).await? {
+        Some(block_number) => db::get_block_by_number(pool, block_number).await,
+        None => Ok(None),
     }
 }
 
+async fn resolve_block_tag(
+    pool: &PgPool,
+    chain_head: &ChainHead,
+    block_tag: BlockTag,
+) -> Result<Option<i64>> {
+    Ok(match block_tag {
+        BlockTag::Number(block_number) => Some(block_number),
+        // The first BTC2 block, or the imported head after a snapshot import
+        BlockTag::Earliest => db::get_first_block_number(pool).await?,
+        // Blocks are final as soon as the sequencer signs them
+        BlockTag::Latest | BlockTag::Pending | BlockTag::Safe | BlockTag::Finalized => {
+            chain_head.block().map(|block| block.number)
+        }
+    })
+}
+
+async fn block_response(
+    pool: &PgPool,
+    block: Option<Block>,
+    include_transactions: bool,
+) -> Result<ResponseValue> {
+    let Some(block) = block else {
+        return Ok(ResponseValue::Null);
+    };
+    let parent_hash = db::get_head_block(pool, block.number - 1)
+        .await?
+        .map(|block| block.hash)
+        .unwrap_or_default();
+    let transactions = db::get_stored_transactions_by_block_number(pool, block.number).await?;
+
+    Ok(ResponseValue::Value(block_object(
+        &block,
+        parent_hash,
+        &transactions,
+        include_transactions,
+    )))
+}
+
+pub fn block_object(
+    block: &Block,
+    parent_hash: [u8; 32],
+    transactions: &[StoredTransaction],
+    include_transactions: bool,
+) -> Value {
+    let mut object = block_header(block, parent_hash);
+    object["gasUsed"] = encode_amount(
+        transactions
+            .iter()
+            .map(|stored| evm::intrinsic_gas(stored.transaction.input()))
+            .sum::<u64>()
+            .into(),
+    );
+    object["transactions"] = transactions
+        .iter()
+        .map(|stored| {
+            if include_transactions {
+                transaction_object(stored)
+            } else {
+                encode_bytes(&stored.hash)
+            }
+        })
+        .collect();
+    object["uncles"] = json!([]);
+
+    object
+}
+
 pub async fn gas_price() -> Result<ResponseValue> {
     Ok(ResponseValue::Number(U256::from::<u64>(0)))
 }
@@ -177,23 +373,95 @@ pub async fn max_priority_fee_per_gas() -> Result<ResponseValue> {
     Ok(ResponseValue::Number(U256::from::<u64>(0)))
 }
 
-pub async fn estimate_gas() -> Result<ResponseValue> {
-    Ok(ResponseValue::Number(U256::from::<i64>(DEFAULT_GAS_LIMIT)))
+pub async fn estimate_gas(transaction: &Value) -> Result<ResponseValue> {
+    let input = match transaction.get("input").or(transaction.get("data")) {
+        Some(Value::String(input)) => parse_bytes_like(input)?,
+        _ => vec![],
+    };
+
+    Ok(ResponseValue::Number(U256::from(evm::intrinsic_gas(
+        &input,
+    ))))
 }
 
-pub async fn call(_data: &Vec<u8>) -> Result<ResponseValue> {
+pub async fn call(_transaction: &Value) -> Result<ResponseValue> {
     Ok(ResponseValue::Null)
 }
 
-pub async fn block_number(pool: PgPool) -> Result<ResponseValue> {
-    let transaction_count = db::get_transaction_count(&pool).await.unwrap_or(0);
-
-    Ok(ResponseValue::Number(U256::from::<u64>(
-        (transaction_count).try_into().unwrap(),
+pub async fn block_number(chain_head: &ChainHead) -> Result<ResponseValue> {
+    Ok(ResponseValue::Number(U256::from::<i64>(
+        chain_head.block_number(),
     )))
 }
 
+pub async fn accounts() -> Result<ResponseValue> {
+    Ok(ResponseValue::Value(json!([])))
+}
+
+// Gas is free and blocks have no gas limit, so every fee and ratio is zero
+pub async fn fee_history(
+    pool: PgPool,
+    chain_head: &ChainHead,
+    block_count: i64,
+    newest_block: BlockTag,
+    reward_percentiles: Vec<f64>,
+) -> Result<ResponseValue> {
+    let newest_block = resolve_block_tag(&pool, chain_head, newest_block)
+        .await?
+        .unwrap_or(chain_head.block_number());
+    let block_count = block_count.clamp(0, MAX_FEE_HISTORY_BLOCKS);
+    let blocks: Vec<Block> =
+        db::get_blocks_after(&pool, newest_block - block_count, Some(block_count))
+            .await?
+            .into_iter()
+            .filter(|block| block.number <= newest_block)
+            .collect();
+    let zero = encode_amount(0u32.into());
+
+    let mut fee_history = json!({
+        "oldestBlock": encode_amount(
+            (blocks.first().map(|block| block.number).unwrap_or(newest_block) as u64).into()
+        ),
+        "baseFeePerGas": vec![zero.clone(); blocks.len() + 1],
+        "gasUsedRatio": vec![0.0; blocks.len()],
+    });
+    if !reward_percentiles.is_empty() {
+        fee_history["reward"] = json!(vec![vec![zero; reward_percentiles.len()]; blocks.len()]);
+    }
+
+    Ok(ResponseValue::Value(fee_history))
+}
+
+pub async fn syncing(chain_head: &ChainHead) -> Result<ResponseValue> {
+    Ok(ResponseValue::Value(match chain_head.sync_status() {
+        Some(sync_status) => json!({
+            "startingBlock": encode_amount((sync_status.starting_block as u64).into()),
+            "currentBlock": encode_amount((chain_head.block_number() as u64).into()),
+            "highestBlock": encode_amount((sync_status.highest_block as u64).into()),
+        }),
+        None => json!(false),
+    }))
+}
+
 pub async fn get_balance(pool: PgPool, address: [u8; 20]) -> Result<ResponseValue> {
-    let balance = db::get_balance(&pool, address).await.unwrap_or(0);
+    let balance = db::get_balance_or_zero(&pool, address).await?;
     Ok(ResponseValue::Number(scale_up(balance)))
 }
+
+pub fn block_header(block: &Block, parent_hash: [u8; 32]) -> Value {
+    json!({
+        "hash": encode_bytes(&block.hash),
+        "parentHash": encode_bytes(&parent_hash),
+        "number": encode_amount((block.number as u64).into()),
+        "miner": encode_bytes(&[0; 20]),
+        "extraData": encode_bytes(&vec![]),
+        "gasLimit": encode_amount(0u32.into()),
+        "gasUsed": encode_amount(0u32.into()),
+        "timestamp": encode_amount((block.timestamp.assume_utc().unix_timestamp() as u64).into()),
+        "difficulty": encode_amount(0u32.into()),
+        "nonce": encode_bytes(&[0; 8]),
+        "mixHash": encode_bytes(&[0; 32]),
+        "sha3Uncles": encode_bytes(&EMPTY_UNCLES_HASH),
+        "logsBloom": encode_bytes(&[0; 256]),
+    })
+}
@@ -0,0 +1,261 @@
+use super::handle_body;
+use crate::{
+    constants::MAX_BODY_SIZE,
+    error::{Error, Result},
+    metrics, AppState,
+};
+use serde_json::Value;
+use std::{
+    fs,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    spawn,
+};
+
+// Access is controlled by the socket's file permissions, so there is no rate
+// limiting or auth. Subscriptions are only available over WebSockets.
+pub async fn serve(state: AppState, path: PathBuf, mode: u32) -> Result<()> {
+    remove_stale_socket(&path).await?;
+    // Bound in a directory only the node's user can enter and moved into place
+    // once `mode` is set, so nobody can connect in between
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let staging = tempfile::Builder::new()
+        .prefix(".bitcoin2-ipc")
+        .tempdir_in(parent)?;
+    let staged_path = staging.path().join("socket");
+    let listener = UnixListener::bind(&staged_path)?;
+    fs::set_permissions(&staged_path, fs::Permissions::from_mode(mode))?;
+    fs::rename(&staged_path, &path)?;
+    drop(staging);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        spawn(handle_connection(state.clone(), stream));
+    }
+}
+
+// A socket is left behind if the node didn't shut down cleanly. Anything
+// else at `path`, or a socket another process is serving, is an error.
+async fn remove_stale_socket(path: &Path) -> Result<()> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(Error::Error(format!(
+            "{} exists and is not a socket",
+            path.display()
+        )));
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(Error::Error(format!("{} is in use", path.display())));
+    }
+
+    Ok(fs::remove_file(path)?)
+}
+
+// Finds where each top level object or array ends without parsing it, so a
+// request that arrives in pieces is only scanned once
+#[derive(Debug, Default)]
+struct Framer {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    scanned: usize,
+}
+
+impl Framer {
+    // The end of the first complete value in `buffer`
+    fn next(&mut self, buffer: &[u8]) -> Result<Option<usize>> {
+        while self.scanned < buffer.len() {
+            let byte = buffer[self.scanned];
+            self.scanned += 1;
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => (),
+                }
+                continue;
+            }
+            match byte {
+                b'"' if self.depth > 0 => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' if self.depth > 0 => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        return Ok(Some(self.scanned));
+                    }
+                }
+                _ if self.depth > 0 || byte.is_ascii_whitespace() => (),
+                _ => {
+                    return Err(Error::InvalidJson(
+                        "Expected an object or an array".to_string(),
+                    ))
+                }
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Requests are JSON objects or arrays written back to back, optionally
+// separated by whitespace, each response is written on its own line
+async fn handle_connection(state: AppState, mut stream: UnixStream) {
+    let mut buffer = vec![];
+    let mut chunk = [0; 8192];
+    let mut framer = Framer::default();
+
+    loop {
+        let read = match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => read,
+        };
+        buffer.extend_from_slice(&chunk[..read]);
+
+        loop {
+            let request = match framer.next(&buffer) {
+                Ok(Some(end)) => {
+                    let request = serde_json::from_slice::<Value>(&buffer[..end])
+                        .map_err(|e| Error::InvalidJson(e.to_string()));
+                    buffer.drain(..end);
+                    framer = Framer::default();
+                    request
+                }
+                // Only whitespace so far
+                Ok(None) if framer.depth == 0 => {
+                    buffer.clear();
+                    framer = Framer::default();
+                    break;
+                }
+                Ok(None) => break,
+                Err(err) => Err(err),
+            };
+            // The stream can't be resynchronized after invalid JSON
+            let request = match request {
+                Ok(request) => request,
+                Err(err) => {
+                    metrics::record_error(&err);
+                    let _ = write(&mut stream, &err.to_json_rpc(Value::Null)).await;
+                    return;
+                }
+            };
+            let response = handle_body(state.clone(), request)
+                .await
+                .unwrap_or_else(|err| Some(err.to_json_rpc(Value::Null)));
+            if let Some(response) = response {
+                if write(&mut stream, &response).await.is_err() {
+                    return;
+                }
+            }
+        }
+        if buffer.len() > *MAX_BODY_SIZE {
+            let err =
+                Error::InvalidRequest(format!("Requests are limited to {} bytes", *MAX_BODY_SIZE));
+            metrics::record_error(&err);
+            let _ = write(&mut stream, &err.to_json_rpc(Value::Null)).await;
+            return;
+        }
+    }
+}
+
+async fn write(stream: &mut UnixStream, response: &Value) -> std::io::Result<()> {
+    let mut bytes = response.to_string().into_bytes();
+    bytes.push(b'\n');
+    stream.write_all(&bytes).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use sqlx::PgPool;
+    use std::time::Duration;
+    use tokio::{io::AsyncBufReadExt, io::BufReader, time::sleep};
+
+    #[sqlx::test]
+    async fn serve(pool: PgPool) -> sqlx::Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bitcoin2.ipc");
+        spawn({
+            let path = path.clone();
+            async move {
+                let state = AppState::load(pool).await.unwrap();
+                super::serve(state, path, 0o600).await.unwrap()
+            }
+        });
+        while !path.exists() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        let request = json!({"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1});
+        // Split across writes and followed by a notification and a batch
+        let message = format!(
+            "{}{}\n[{}]",
+            request,
+            json!({"jsonrpc": "2.0", "method": "eth_chainId"}),
+            request
+        );
+        let (first, second) = message.split_at(10);
+        stream.write_all(first.as_bytes()).await.unwrap();
+        stream.flush().await.unwrap();
+        stream.write_all(second.as_bytes()).await.unwrap();
+
+        let mut lines = BufReader::new(stream).lines();
+        let response: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["result"], "0xb2");
+        let response: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response[0]["result"], "0xb2");
+
+        // An unfinished request can't grow past MAX_BODY_SIZE
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        let message = format!("[{}", " ".repeat(*MAX_BODY_SIZE));
+        let _ = stream.write_all(message.as_bytes()).await;
+        let mut lines = BufReader::new(stream).lines();
+        let response: Value =
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], -32600);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn existing_path(pool: PgPool) -> sqlx::Result<()> {
+        let state = AppState::load(pool).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bitcoin2.ipc");
+
+        fs::write(&path, "").unwrap();
+        assert!(super::serve(state.clone(), path.clone(), 0o600)
+            .await
+            .is_err());
+        assert!(fs::metadata(&path).unwrap().is_file());
+        fs::remove_file(&path).unwrap();
+
+        // Left behind by a listener that is gone
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        spawn(super::serve(state.clone(), path.clone(), 0o600));
+        while UnixStream::connect(&path).await.is_err() {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(super::serve(state, path, 0o600).await.is_err());
+
+        Ok(())
+    }
+}
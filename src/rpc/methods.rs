@@ -0,0 +1,306 @@
+use super::ParamValue;
+use crate::error::{Error, Result};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    Address,
+    BitcoinAddress,
+    Hash,
+    Bytes,
+    Quantity,
+    BlockTag,
+    Bool,
+    Array,
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefaultValue {
+    Latest,
+    False,
+    EmptyArray,
+    EmptyObject,
+}
+
+#[derive(Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ParamKind,
+    // `None` for required params
+    pub default: Option<DefaultValue>,
+}
+
+#[derive(Debug)]
+pub struct Method {
+    pub name: &'static str,
+    pub params: &'static [Param],
+}
+
+const fn required(name: &'static str, kind: ParamKind) -> Param {
+    Param {
+        name,
+        kind,
+        default: None,
+    }
+}
+
+const fn optional(name: &'static str, kind: ParamKind, default: DefaultValue) -> Param {
+    Param {
+        name,
+        kind,
+        default: Some(default),
+    }
+}
+
+const fn method(name: &'static str, params: &'static [Param]) -> Method {
+    Method { name, params }
+}
+
+const ADDRESS: Param = required("address", ParamKind::Address);
+const BLOCK: Param = optional("block", ParamKind::BlockTag, DefaultValue::Latest);
+const TRANSACTION: Param = required("transaction", ParamKind::Object);
+const BLOCK_HASH: Param = required("blockHash", ParamKind::Hash);
+const BLOCK_TAG: Param = required("block", ParamKind::BlockTag);
+const INDEX: Param = required("index", ParamKind::Quantity);
+const TRANSACTION_HASH: Param = required("transactionHash", ParamKind::Hash);
+const INCLUDE_TRANSACTIONS: Param =
+    optional("includeTransactions", ParamKind::Bool, DefaultValue::False);
+
+pub const METHODS: &[Method] = &[
+    method("rpc.discover", &[]),
+    method("net_version", &[]),
+    method("net_listening", &[]),
+    method("net_peerCount", &[]),
+    method("web3_clientVersion", &[]),
+    method("web3_sha3", &[required("data", ParamKind::Bytes)]),
+    method("eth_blockNumber", &[]),
+    method("eth_call", &[TRANSACTION, BLOCK]),
+    method("eth_chainId", &[]),
+    method("eth_estimateGas", &[TRANSACTION, BLOCK]),
+    method("eth_gasPrice", &[]),
+    method("eth_getBalance", &[ADDRESS, BLOCK]),
+    method("eth_getBlockByHash", &[BLOCK_HASH, INCLUDE_TRANSACTIONS]),
+    method("eth_getBlockByNumber", &[BLOCK_TAG, INCLUDE_TRANSACTIONS]),
+    method("eth_getBlockTransactionCountByHash", &[BLOCK_HASH]),
+    method("eth_getBlockTransactionCountByNumber", &[BLOCK_TAG]),
+    method(
+        "eth_getTransactionByBlockHashAndIndex",
+        &[BLOCK_HASH, INDEX],
+    ),
+    method(
+        "eth_getTransactionByBlockNumberAndIndex",
+        &[BLOCK_TAG, INDEX],
+    ),
+    method("eth_getBlockReceipts", &[BLOCK_TAG]),
+    method(
+        "eth_feeHistory",
+        &[
+            required("blockCount", ParamKind::Quantity),
+            required("newestBlock", ParamKind::BlockTag),
+            optional(
+                "rewardPercentiles",
+                ParamKind::Array,
+                DefaultValue::EmptyArray,
+            ),
+        ],
+    ),
+    method("eth_accounts", &[]),
+    method("eth_getCode", &[ADDRESS, BLOCK]),
+    method("eth_getTransactionCount", &[ADDRESS, BLOCK]),
+    method("eth_getTransactionByHash", &[TRANSACTION_HASH]),
+    method("eth_getTransactionReceipt", &[TRANSACTION_HASH]),
+    method(
+        "eth_sendRawTransaction",
+        &[required("transaction", ParamKind::Bytes)],
+    ),
+    method("eth_maxPriorityFeePerGas", &[]),
+    method("eth_syncing", &[]),
+    method(
+        "btc2_getLedger",
+        &[
+            ADDRESS,
+            optional("options", ParamKind::Object, DefaultValue::EmptyObject),
+        ],
+    ),
+    method("btc2_getAnchors", &[]),
+    method("btc2_getSupply", &[]),
+    method("btc2_getUpgradeStats", &[]),
+    method(
+        "btc2_buildUpgradeMessage",
+        &[
+            required("destination", ParamKind::Address),
+            required("outpoints", ParamKind::Array),
+        ],
+    ),
+    method(
+        "btc2_getLegacyBalance",
+        &[required("address", ParamKind::BitcoinAddress)],
+    ),
+    method(
+        "btc2_getLegacyOutput",
+        &[
+            required("txid", ParamKind::Hash),
+            required("vout", ParamKind::Quantity),
+        ],
+    ),
+    method(
+        "btc2_getRawBlocks",
+        &[
+            required("blockNumber", ParamKind::Quantity),
+            required("count", ParamKind::Quantity),
+        ],
+    ),
+];
+
+// Only served on the admin port, see `rpc::admin`
+pub const ADMIN_METHODS: &[Method] = &[
+    method("admin_status", &[]),
+    method("admin_pauseIntake", &[]),
+    method("admin_resumeIntake", &[]),
+    method("admin_pauseBlockProduction", &[]),
+    method("admin_resumeBlockProduction", &[]),
+    method(
+        "admin_setBlockPolicy",
+        &[required("policy", ParamKind::Object)],
+    ),
+    method("admin_dropTransaction", &[TRANSACTION_HASH]),
+    method("admin_exportSnapshot", &[]),
+];
+
+pub fn find(name: &str) -> Result<&'static Method> {
+    find_in(METHODS, name)
+}
+
+pub fn find_admin(name: &str) -> Result<&'static Method> {
+    find_in(ADMIN_METHODS, name)
+}
+
+fn find_in(methods: &'static [Method], name: &str) -> Result<&'static Method> {
+    methods
+        .iter()
+        .find(|method| method.name == name)
+        .ok_or(Error::UnsupportedMethod(name.to_string()))
+}
+
+impl DefaultValue {
+    fn value(self) -> Value {
+        match self {
+            Self::Latest => json!("latest"),
+            Self::False => json!(false),
+            Self::EmptyArray => json!([]),
+            Self::EmptyObject => json!({}),
+        }
+    }
+}
+
+impl ParamKind {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            Self::Bool => value.is_boolean(),
+            Self::Array => value.is_array(),
+            Self::Object => value.is_object(),
+            // Some wallets send plain numbers
+            Self::Quantity => value.is_string() || value.is_u64(),
+            _ => value.is_string(),
+        }
+    }
+}
+
+impl Method {
+    // Accepts positional or by-name params and fills in defaults for missing trailing ones
+    pub fn params(&self, params: Option<Value>) -> Result<Vec<ParamValue>> {
+        let mut values: Vec<Option<Value>> = match params {
+            None | Some(Value::Null) => vec![],
+            Some(Value::Array(values)) if values.len() <= self.params.len() => {
+                values.into_iter().map(Some).collect()
+            }
+            Some(Value::Array(values)) => {
+                return Err(Error::ParseError(format!(
+                    "{} takes at most {} params but got {}",
+                    self.name,
+                    self.params.len(),
+                    values.len()
+                )))
+            }
+            Some(Value::Object(mut values)) => {
+                let named = self
+                    .params
+                    .iter()
+                    .map(|param| values.remove(param.name))
+                    .collect();
+                if let Some(name) = values.keys().next() {
+                    return Err(Error::ParseError(format!(
+                        "{} has no param {}",
+                        self.name, name
+                    )));
+                }
+                named
+            }
+            Some(_) => {
+                return Err(Error::ParseError(
+                    "Params must be an array or an object".to_string(),
+                ))
+            }
+        };
+        values.resize(self.params.len(), None);
+
+        self.params
+            .iter()
+            .zip(values)
+            .map(|(param, value)| match (value, param.default) {
+                (Some(value), _) if !value.is_null() => {
+                    if param.kind.matches(&value) {
+                        Ok(ParamValue(value))
+                    } else {
+                        Err(Error::ParseError(format!(
+                            "Invalid {} param {}",
+                            self.name, param.name
+                        )))
+                    }
+                }
+                (_, Some(default)) => Ok(ParamValue(default.value())),
+                (_, None) => Err(Error::ParseError(format!(
+                    "Missing {} param {}",
+                    self.name, param.name
+                ))),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params() {
+        let get_balance = find("eth_getBalance").unwrap();
+        let address = json!("0xf204ee5596cabc6ec60e5e92fd412ea7f856b625");
+
+        for params in [
+            Some(json!([address])),
+            Some(json!([address, null])),
+            Some(json!({ "address": address })),
+        ] {
+            let params = get_balance.params(params).unwrap();
+            assert_eq!(params[0].0, address);
+            assert_eq!(params[1].0, json!("latest"));
+        }
+        assert_eq!(
+            find("eth_blockNumber").unwrap().params(None).unwrap().len(),
+            0
+        );
+
+        for params in [
+            None,
+            Some(json!([address, "latest", true])),
+            Some(json!({ "account": address })),
+            Some(json!([true])),
+        ] {
+            assert_eq!(get_balance.params(params).unwrap_err().code(), -32602);
+        }
+        assert_eq!(find("eth_unknown").unwrap_err().code(), -32601);
+        assert_eq!(find("admin_pauseIntake").unwrap_err().code(), -32601);
+    }
+}
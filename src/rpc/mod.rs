@@ -1,25 +1,61 @@
+pub mod admin;
 mod btc2;
+pub mod client;
 mod eth;
+pub mod ipc;
+pub mod methods;
 mod net;
+pub mod openrpc;
+pub mod rest;
+mod web3;
+pub mod ws;
 
-use axum::{extract, extract::State};
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
 
 use btc2::*;
 use eth::*;
 use net::*;
+use web3::*;
 
-use crate::error::{Error, Result};
+use crate::{
+    constants::{MAX_BATCH_SIZE, PRIMARY_URL},
+    error::{Error, Result},
+    metrics,
+    rate_limit::{self, Client},
+    AppState,
+};
 use num_bigint::BigUint;
 use num_traits::ToPrimitive;
 use reth_primitives::U256;
+use serde::{Deserialize, Deserializer};
 use serde_json::{json, Value};
-use sqlx::PgPool;
+use std::{future::Future, net::SocketAddr, time::Instant};
+
+// Followers forward these to the primary instead of handling them
+const WRITE_METHODS: &[&str] = &["eth_sendRawTransaction"];
 
 #[derive(serde::Deserialize, Debug)]
 pub struct JsonRpcRequest {
-    id: Value,
+    // `None` for notifications, which get no response
+    #[serde(default, deserialize_with = "deserialize_id")]
+    id: Option<Value>,
     method: String,
-    params: Vec<Value>,
+    // Positional or by-name, see `methods::Method::params`
+    #[serde(default)]
+    params: Option<Value>,
+}
+
+// Distinguishes `"id": null` from a missing id
+fn deserialize_id<'de, D>(deserializer: D) -> std::result::Result<Option<Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Some(Value::deserialize(deserializer)?))
 }
 
 #[derive(Debug)]
@@ -49,6 +85,12 @@ impl ResponseValue {
     }
 }
 
+impl From<Option<Value>> for ResponseValue {
+    fn from(value: Option<Value>) -> Self {
+        value.map(Self::Value).unwrap_or(Self::Null)
+    }
+}
+
 #[derive(Debug)]
 pub struct ParamValue(Value);
 
@@ -57,7 +99,9 @@ impl TryFrom<&ParamValue> for [u8; 20] {
 
     fn try_from(request_value: &ParamValue) -> Result<Self> {
         if let Value::String(string) = &request_value.0 {
-            Ok(parse_bytes_like(string)?.try_into()?)
+            Ok(parse_bytes_like(string)?
+                .try_into()
+                .map_err(|_| Error::ParseError(format!("Expected 20 byte length array")))?)
         } else {
             Err(Error::ParseError(format!(
                 "Expected 20 byte length array 4"
@@ -69,13 +113,49 @@ impl TryFrom<&ParamValue> for i64 {
     type Error = Error;
 
     fn try_from(request_value: &ParamValue) -> Result<Self> {
-        if let Value::String(string) = &request_value.0 {
-            Ok(parse_i64(string)?)
-        } else {
-            Err(Error::ParseError(format!("Expected i64")))
+        match &request_value.0 {
+            Value::String(string) => Ok(parse_i64(string)?),
+            Value::Number(number) => number
+                .as_i64()
+                .ok_or(Error::ParseError(format!("Expected i64"))),
+            _ => Err(Error::ParseError(format!("Expected i64"))),
         }
     }
 }
+impl TryFrom<&ParamValue> for Vec<f64> {
+    type Error = Error;
+
+    fn try_from(request_value: &ParamValue) -> Result<Self> {
+        request_value
+            .0
+            .as_array()
+            .and_then(|values| values.iter().map(Value::as_f64).collect())
+            .ok_or(Error::ParseError(
+                "Expected an array of numbers".to_string(),
+            ))
+    }
+}
+impl TryFrom<&ParamValue> for String {
+    type Error = Error;
+
+    fn try_from(request_value: &ParamValue) -> Result<Self> {
+        request_value
+            .0
+            .as_str()
+            .map(str::to_string)
+            .ok_or(Error::ParseError("Expected string".to_string()))
+    }
+}
+impl TryFrom<&ParamValue> for bool {
+    type Error = Error;
+
+    fn try_from(request_value: &ParamValue) -> Result<Self> {
+        request_value
+            .0
+            .as_bool()
+            .ok_or(Error::ParseError("Expected bool".to_string()))
+    }
+}
 #[derive(Debug)]
 enum BlockTag {
     Latest,
@@ -113,7 +193,9 @@ impl TryFrom<&ParamValue> for [u8; 32] {
     type Error = Error;
 
     fn try_from(request_value: &ParamValue) -> Result<Self> {
-        Ok(Vec::<u8>::try_from(request_value)?.try_into()?)
+        Ok(Vec::<u8>::try_from(request_value)?
+            .try_into()
+            .map_err(|_| Error::ParseError(format!("Expected 32 byte length array")))?)
     }
 }
 
@@ -154,51 +236,355 @@ pub fn parse_bytes_like(value: &str) -> Result<Vec<u8>> {
 }
 
 pub async fn handler(
-    State(pool): State<PgPool>,
-    extract::Json(request): extract::Json<JsonRpcRequest>,
-) -> axum::response::Result<axum::Json<Value>> {
-    // println!("{:?}", request);
-    let params: Vec<ParamValue> = request.params.into_iter().map(ParamValue).collect();
-    let result = match (request.method.as_ref(), params[..].as_ref()) {
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> axum::response::Result<Response> {
+    let body: Value = serde_json::from_slice(&body)
+        .map_err(|e| Error::InvalidJson(e.to_string()))
+        .inspect_err(metrics::record_error)?;
+    // Batches are charged for every call up front
+    let cost = match &body {
+        Value::Array(requests) => requests.iter().map(request_cost).sum(),
+        request => request_cost(request),
+    };
+    if let Some(client) = Client::new(connect_info, &headers) {
+        rate_limit::check(&client, cost)?;
+    }
+
+    Ok(match handle_body(state, body).await? {
+        Some(responses) => axum::Json(responses).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    })
+}
+
+// A single request or a batch, `None` when there is nothing to respond with
+pub async fn handle_body(state: AppState, body: Value) -> Result<Option<Value>> {
+    respond(body, |request| handle_request(state.clone(), request)).await
+}
+
+// Batches and notifications for every transport, `handle` runs one request
+// and returns its response object. Errors returned from here are recorded,
+// since no method ran.
+pub async fn respond<F, R>(body: Value, mut handle: F) -> Result<Option<Value>>
+where
+    F: FnMut(JsonRpcRequest) -> R,
+    R: Future<Output = Result<Value>>,
+{
+    Ok(match body {
+        Value::Array(requests) => handle_batch(requests, handle)
+            .await
+            .inspect_err(metrics::record_error)?,
+        request => {
+            let request: JsonRpcRequest = serde_json::from_value(request)
+                .map_err(|e| Error::InvalidRequest(e.to_string()))
+                .inspect_err(metrics::record_error)?;
+            let id = request.id.clone();
+            let response = handle(request).await;
+            id.map(|id| response.unwrap_or_else(|err| err.to_json_rpc(id)))
+        }
+    })
+}
+
+fn request_cost(request: &Value) -> u64 {
+    rate_limit::cost(
+        request
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default(),
+    )
+}
+
+// Calls run one after another so transactions in a batch apply in order
+async fn handle_batch<F, R>(requests: Vec<Value>, mut handle: F) -> Result<Option<Value>>
+where
+    F: FnMut(JsonRpcRequest) -> R,
+    R: Future<Output = Result<Value>>,
+{
+    if requests.is_empty() {
+        return Err(Error::InvalidRequest("Empty batch".to_string()));
+    }
+    if requests.len() > *MAX_BATCH_SIZE {
+        return Err(Error::InvalidRequest(format!(
+            "Batches are limited to {} requests",
+            *MAX_BATCH_SIZE
+        )));
+    }
+
+    let mut responses = vec![];
+    for request in requests {
+        let request: JsonRpcRequest = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(err) => {
+                let err = Error::InvalidRequest(err.to_string());
+                metrics::record_error(&err);
+                responses.push(err.to_json_rpc(Value::Null));
+                continue;
+            }
+        };
+        let Some(id) = request.id.clone() else {
+            let _ = handle(request).await;
+            continue;
+        };
+        responses.push(
+            handle(request)
+                .await
+                .unwrap_or_else(|err| err.to_json_rpc(id)),
+        );
+    }
+
+    Ok((!responses.is_empty()).then_some(Value::Array(responses)))
+}
+
+// Shared by every transport, returns the whole response object
+#[tracing::instrument(
+    name = "rpc",
+    skip_all,
+    fields(method = %request.method, id = ?request.id)
+)]
+pub async fn handle_request(state: AppState, request: JsonRpcRequest) -> Result<Value> {
+    tracing::debug!(params = ?request.params, "request");
+    if let Some(primary_url) = PRIMARY_URL.as_ref() {
+        if WRITE_METHODS.contains(&request.method.as_str()) {
+            return forward(primary_url, &request)
+                .await
+                .inspect_err(metrics::record_error);
+        }
+    }
+    let method = methods::find(&request.method).inspect_err(metrics::record_error)?;
+    let started_at = Instant::now();
+    let result = call_method(state, method, request.params).await;
+    metrics::record_request(method.name, started_at.elapsed(), result.as_ref().err());
+    match &result {
+        Ok(result) => tracing::debug!(?result, "response"),
+        // The caller's mistake, like invalid params, not something to act on
+        Err(err) if err.code() != -32603 && (-32699..=-32600).contains(&err.code()) => {
+            tracing::debug!(error = %err, "failed")
+        }
+        Err(err) => tracing::warn!(error = %err, "failed"),
+    }
+
+    Ok(json!({
+    "jsonrpc": "2.0",
+    "id": request.id,
+    "result": result?
+    }))
+}
+
+// The primary's response, errors included, is passed back unchanged
+async fn forward(primary_url: &str, request: &JsonRpcRequest) -> Result<Value> {
+    client::forward(
+        primary_url,
+        &json!({
+            "jsonrpc": "2.0",
+            "id": request.id,
+            "method": request.method,
+            "params": request.params,
+        }),
+    )
+    .await
+}
+
+async fn call_method(
+    state: AppState,
+    method: &methods::Method,
+    params: Option<Value>,
+) -> Result<Value> {
+    let params = method.params(params)?;
+
+    dispatch(state, method.name, &params).await?.to_value()
+}
+
+// `params` has already been checked against the method's entry in `methods::METHODS`
+async fn dispatch(state: AppState, method: &str, params: &[ParamValue]) -> Result<ResponseValue> {
+    let AppState { pool, chain_head } = state;
+
+    Ok(match (method, params) {
         ("net_version", []) => version().await?,
-        ("eth_blockNumber", []) => block_number(pool).await?,
-        ("eth_call", [data]) => call(&data.try_into()?).await?,
+        ("rpc.discover", []) => openrpc::discover().await?,
+        ("net_listening", []) => listening().await?,
+        ("net_peerCount", []) => peer_count().await?,
+        ("web3_clientVersion", []) => client_version().await?,
+        ("web3_sha3", [data]) => sha3(data.try_into()?).await?,
+        ("eth_accounts", []) => accounts().await?,
+        ("eth_blockNumber", []) => block_number(&chain_head).await?,
+        ("eth_call", [transaction, _block]) => call(&transaction.0).await?,
         ("eth_chainId", []) => chain_id().await?,
-        ("eth_estimateGas", [_params]) => estimate_gas().await?,
+        ("eth_estimateGas", [transaction, _block]) => estimate_gas(&transaction.0).await?,
         ("eth_gasPrice", []) => gas_price().await?,
         ("eth_getBalance", [address, _block_identifier]) => {
             get_balance(pool, address.try_into()?).await?
         }
-        ("btc2_getLedger", [address]) => get_transactions(pool, address.try_into()?).await?,
-        ("eth_getBlockByHash", [block_hash, _include_full_transactions]) => {
-            get_block_by_hash(pool, block_hash.try_into()?).await?
+        ("btc2_getLedger", [address, options]) => {
+            get_ledger(pool, address.try_into()?, &options.0).await?
+        }
+        ("btc2_getAnchors", []) => get_anchors(pool).await?,
+        ("btc2_getSupply", []) => get_supply(pool).await?,
+        ("btc2_getUpgradeStats", []) => get_upgrade_stats(pool).await?,
+        ("btc2_buildUpgradeMessage", [destination, outpoints]) => {
+            build_upgrade_message(pool, destination.try_into()?, &outpoints.0).await?
+        }
+        ("btc2_getLegacyBalance", [address]) => {
+            get_legacy_balance(pool, address.try_into()?).await?
+        }
+        ("btc2_getLegacyOutput", [txid, vout]) => {
+            let txid = parse_txid(&String::try_from(txid)?)?;
+            get_legacy_output(pool, txid, vout.try_into()?).await?
         }
-        ("eth_getBlockByNumber", [block_number, _include_full_transactions]) => {
-            get_block_by_number(pool, block_number.try_into()?).await?
+        ("btc2_getRawBlocks", [block_number, count]) => {
+            get_raw_blocks(pool, block_number.try_into()?, count.try_into()?).await?
         }
-        ("eth_getCode", [block_hash]) => get_code(block_hash.try_into()?).await?,
+        ("eth_getBlockByHash", [block_hash, include_transactions]) => {
+            get_block_by_hash(pool, block_hash.try_into()?, include_transactions.try_into()?)
+                .await?
+        }
+        ("eth_getBlockByNumber", [block_tag, include_transactions]) => {
+            get_block_by_number(
+                pool,
+                &chain_head,
+                block_tag.try_into()?,
+                include_transactions.try_into()?,
+            )
+            .await?
+        }
+        ("eth_getBlockTransactionCountByNumber", [block_tag]) => {
+            get_block_transaction_count_by_number(pool, &chain_head, block_tag.try_into()?).await?
+        }
+        ("eth_getBlockTransactionCountByHash", [block_hash]) => {
+            get_block_transaction_count_by_hash(pool, block_hash.try_into()?).await?
+        }
+        ("eth_getTransactionByBlockNumberAndIndex", [block_tag, index]) => {
+            get_transaction_by_block_number_and_index(
+                pool,
+                &chain_head,
+                block_tag.try_into()?,
+                index.try_into()?,
+            )
+            .await?
+        }
+        ("eth_getTransactionByBlockHashAndIndex", [block_hash, index]) => {
+            get_transaction_by_block_hash_and_index(
+                pool,
+                block_hash.try_into()?,
+                index.try_into()?,
+            )
+            .await?
+        }
+        ("eth_getBlockReceipts", [block_tag]) => {
+            get_block_receipts(pool, &chain_head, block_tag.try_into()?).await?
+        }
+        ("eth_feeHistory", [block_count, newest_block, reward_percentiles]) => {
+            fee_history(
+                pool,
+                &chain_head,
+                block_count.try_into()?,
+                newest_block.try_into()?,
+                reward_percentiles.try_into()?,
+            )
+            .await?
+        }
+        ("eth_getCode", [address, _block]) => get_code(address.try_into()?).await?,
         ("eth_getTransactionCount", [address, _block_number]) => {
             get_transaction_count(pool, address.try_into()?).await?
         }
         ("eth_getTransactionByHash", [transaction_hash]) => {
-            get_transaction_by_hash(transaction_hash.try_into()?).await?
+            get_transaction_by_hash(pool, transaction_hash.try_into()?).await?
         }
-        ("eth_getTransactionReceipt", [block_hash]) => {
-            get_transaction_receipt(block_hash.try_into()?).await?
+        ("eth_getTransactionReceipt", [transaction_hash]) => {
+            get_transaction_receipt(pool, transaction_hash.try_into()?).await?
         }
         ("eth_sendRawTransaction", [raw_transaction]) => {
             send_raw_transaction(pool, raw_transaction.try_into()?).await?
         }
+        ("eth_syncing", []) => syncing(&chain_head).await?,
         ("eth_maxPriorityFeePerGas", []) => {
             max_priority_fee_per_gas().await?
         }
-        _ => return Err(Error::UnsupportedMethod(request.method.to_string()).into()), // Err(Error {
-    };
-    // println!("{:?}", &result);
+        _ => return Err(Error::UnsupportedMethod(method.to_string())), // Err(Error {
+    })
+}
 
-    Ok(axum::Json(json!({
-    "jsonrpc": "2.0",
-    "id": request.id,
-    "result": result.to_value()?
-    })))
+#[cfg(test)]
+mod tests {
+    use super::methods::{ParamKind, METHODS};
+    use crate::{app, db, error::Error, evm::Evm, AppState};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn dispatched(pool: PgPool) -> sqlx::Result<()> {
+        let state = AppState::load(pool).await.unwrap();
+
+        for method in METHODS {
+            let params: Vec<Value> = method
+                .params
+                .iter()
+                .map(|param| match param.kind {
+                    ParamKind::Address => json!(format!("0x{}", "00".repeat(20))),
+                    ParamKind::BitcoinAddress => json!("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2"),
+                    ParamKind::Hash => json!(format!("0x{}", "00".repeat(32))),
+                    ParamKind::Bytes => json!("0x"),
+                    ParamKind::Quantity => json!("0x0"),
+                    ParamKind::BlockTag => json!("latest"),
+                    ParamKind::Bool => json!(false),
+                    ParamKind::Array => json!([]),
+                    ParamKind::Object => json!({}),
+                })
+                .collect();
+            let params = method.params(Some(json!(params))).unwrap();
+
+            // Placeholder params may fail, but every method must be routed
+            assert!(
+                !matches!(
+                    super::dispatch(state.clone(), method.name, &params).await,
+                    Err(Error::UnsupportedMethod(_))
+                ),
+                "{} is not dispatched",
+                method.name
+            );
+        }
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn forward(pool: PgPool) -> sqlx::Result<()> {
+        Evm::new(pool.clone())
+            .deposit(
+                hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625").into(),
+                100000000,
+            )
+            .await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_url = format!("http://{}", listener.local_addr().unwrap());
+        let primary = app(pool.clone()).await;
+        tokio::spawn(async move { axum::serve(listener, primary).await.unwrap() });
+        let request = serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "method": "eth_sendRawTransaction",
+            "params": ["0xf8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb"],
+            "id": 7
+        }))
+        .unwrap();
+
+        let response = super::forward(&primary_url, &request).await.unwrap();
+        assert_eq!(response["id"], 7);
+        assert_eq!(
+            response["result"],
+            "0x411522bfa6c409da0c2fb3d04246131dd7bc440cccca2dac95e77157357415fd"
+        );
+        assert!(db::get_transaction_id_by_hash(
+            &pool,
+            hex_lit::hex!("411522bfa6c409da0c2fb3d04246131dd7bc440cccca2dac95e77157357415fd")
+        )
+        .await
+        .unwrap()
+        .is_some());
+
+        // Rejected by the primary, the error comes back as its response
+        let response = super::forward(&primary_url, &request).await.unwrap();
+        assert_eq!(response["id"], 7);
+        assert!(response.get("error").is_some());
+        Ok(())
+    }
 }
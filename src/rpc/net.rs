@@ -1,8 +1,18 @@
 use super::ResponseValue;
+use crate::error::Result;
 
-use axum::response::Result;
 use reth_primitives::U256;
+use serde_json::json;
 
 pub async fn version() -> Result<ResponseValue> {
     Ok(ResponseValue::Number(U256::from::<i64>(2)))
 }
+
+pub async fn listening() -> Result<ResponseValue> {
+    Ok(ResponseValue::Value(json!(true)))
+}
+
+// Blocks come from the sequencer rather than a peer to peer network
+pub async fn peer_count() -> Result<ResponseValue> {
+    Ok(ResponseValue::Number(U256::ZERO))
+}
@@ -0,0 +1,508 @@
+use super::{
+    methods::{Method, ParamKind, METHODS},
+    ResponseValue,
+};
+use crate::{db::LedgerEntryType, error::Result};
+use axum::Json;
+use serde_json::{json, Value};
+
+// https://spec.open-rpc.org/
+const OPENRPC_VERSION: &str = "1.2.6";
+
+pub async fn discover() -> Result<ResponseValue> {
+    Ok(ResponseValue::Value(document()))
+}
+
+// Served at a static URL for tools that don't speak JSON-RPC
+pub async fn handler() -> Json<Value> {
+    Json(document())
+}
+
+pub fn document() -> Value {
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": {
+            "title": "Bitcoin 2",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "methods": METHODS
+            .iter()
+            .map(|method| {
+                // Any result, the `documented` test keeps this from being reached
+                let (summary, result) = documentation(method.name).unwrap_or(("", json!({})));
+                method_object(method, summary, result)
+            })
+            .collect::<Vec<Value>>(),
+    })
+}
+
+fn method_object(method: &Method, summary: &str, result: Value) -> Value {
+    json!({
+        "name": method.name,
+        "summary": summary,
+        "paramStructure": "either",
+        "params": method
+            .params
+            .iter()
+            .map(|param| {
+                json!({
+                    "name": param.name,
+                    "required": param.default.is_none(),
+                    "schema": param_schema(param.kind),
+                })
+            })
+            .collect::<Vec<Value>>(),
+        "result": {
+            "name": "result",
+            "schema": result,
+        },
+    })
+}
+
+fn param_schema(kind: ParamKind) -> Value {
+    match kind {
+        ParamKind::Address => address(),
+        ParamKind::BitcoinAddress => json!({ "type": "string", "title": "Bitcoin address" }),
+        ParamKind::Hash => hash(),
+        ParamKind::Bytes => bytes(),
+        ParamKind::Quantity => json!({ "oneOf": [quantity(), { "type": "integer" }] }),
+        ParamKind::BlockTag => json!({
+            "oneOf": [
+                { "type": "string", "enum": ["latest", "earliest", "pending", "safe", "finalized"] },
+                quantity(),
+            ],
+        }),
+        ParamKind::Bool => boolean(),
+        ParamKind::Array => json!({ "type": "array" }),
+        ParamKind::Object => json!({ "type": "object" }),
+    }
+}
+
+fn address() -> Value {
+    json!({ "type": "string", "pattern": "^0x[0-9a-fA-F]{40}$" })
+}
+
+fn hash() -> Value {
+    json!({ "type": "string", "pattern": "^(0x)?[0-9a-fA-F]{64}$" })
+}
+
+fn bytes() -> Value {
+    json!({ "type": "string", "pattern": "^0x[0-9a-fA-F]*$" })
+}
+
+fn quantity() -> Value {
+    json!({ "type": "string", "pattern": "^0x([1-9a-f][0-9a-f]*|0)$" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer" })
+}
+
+// Lowercase hex in display order, without a 0x prefix
+fn txid() -> Value {
+    json!({ "type": "string", "pattern": "^[0-9a-f]{64}$" })
+}
+
+fn script_type() -> Value {
+    json!({
+        "type": "string",
+        "enum": ["p2pkh", "p2sh", "p2pk", "p2wpkh", "p2wsh", "p2tr", "nonstandard"],
+    })
+}
+
+// Every property is required unless the caller removes it from `required`
+fn object(title: &str, properties: Value) -> Value {
+    let required: Vec<&String> = properties.as_object().unwrap().keys().collect();
+
+    json!({
+        "type": "object",
+        "title": title,
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({ "oneOf": [schema, { "type": "null" }] })
+}
+
+fn transaction() -> Value {
+    object(
+        "Transaction",
+        json!({
+            "hash": hash(),
+            "blockHash": nullable(hash()),
+            "blockNumber": nullable(quantity()),
+            "transactionIndex": nullable(quantity()),
+            "from": address(),
+            "to": nullable(address()),
+            "nonce": quantity(),
+            "gas": quantity(),
+            "gasPrice": quantity(),
+            "value": quantity(),
+            "input": bytes(),
+            "type": quantity(),
+            "chainId": quantity(),
+            "v": quantity(),
+            "r": quantity(),
+            "s": quantity(),
+        }),
+    )
+}
+
+fn block() -> Value {
+    object(
+        "Block",
+        json!({
+            "hash": hash(),
+            "parentHash": hash(),
+            "number": quantity(),
+            "miner": address(),
+            "extraData": bytes(),
+            "gasLimit": quantity(),
+            "gasUsed": quantity(),
+            "timestamp": quantity(),
+            "difficulty": quantity(),
+            "nonce": bytes(),
+            "mixHash": hash(),
+            "sha3Uncles": hash(),
+            "logsBloom": bytes(),
+            // Hashes unless `includeTransactions` is set
+            "transactions": array(json!({ "oneOf": [hash(), transaction()] })),
+            "uncles": array(hash()),
+        }),
+    )
+}
+
+fn receipt() -> Value {
+    object(
+        "Receipt",
+        json!({
+            "transactionHash": hash(),
+            "transactionIndex": quantity(),
+            "blockHash": hash(),
+            "blockNumber": quantity(),
+            "from": address(),
+            "to": nullable(address()),
+            "cumulativeGasUsed": quantity(),
+            "gasUsed": quantity(),
+            "effectiveGasPrice": quantity(),
+            "contractAddress": { "type": "null" },
+            "logs": { "type": "array", "maxItems": 0 },
+            "logsBloom": bytes(),
+            "type": quantity(),
+            "status": quantity(),
+        }),
+    )
+}
+
+fn fee_history() -> Value {
+    let mut schema = object(
+        "Fee history",
+        json!({
+            "oldestBlock": quantity(),
+            "baseFeePerGas": array(quantity()),
+            "gasUsedRatio": array(json!({ "type": "number" })),
+            "reward": array(array(quantity())),
+        }),
+    );
+    // Only there when reward percentiles are requested
+    schema["required"] = json!(["oldestBlock", "baseFeePerGas", "gasUsedRatio"]);
+
+    schema
+}
+
+fn ledger_entry() -> Value {
+    object(
+        "Ledger entry",
+        json!({
+            "id": quantity(),
+            "transactionHash": hash(),
+            "blockNumber": nullable(quantity()),
+            "timestamp": nullable(quantity()),
+            "type": {
+                "type": "string",
+                "enum": LedgerEntryType::ALL.map(LedgerEntryType::name),
+            },
+            "direction": { "type": "string", "enum": ["in", "out", "self"] },
+            "creditor": address(),
+            "debtor": address(),
+            "value": quantity(),
+        }),
+    )
+}
+
+fn anchor() -> Value {
+    object(
+        "Anchor",
+        json!({
+            "blockNumber": quantity(),
+            "blockHash": hash(),
+            "txid": hash(),
+            "psbt": { "type": "string", "contentEncoding": "base64" },
+            "status": { "type": "string", "enum": ["pending", "confirmed"] },
+            "bitcoinBlockHash": nullable(hash()),
+            "bitcoinBlockHeight": nullable(integer()),
+        }),
+    )
+}
+
+fn upgrade_totals(title: &str) -> Value {
+    object(
+        title,
+        json!({
+            "upgraded": quantity(),
+            "outpoints": quantity(),
+            "recipients": quantity(),
+        }),
+    )
+}
+
+fn upgrade_stats() -> Value {
+    let mut schema = upgrade_totals("Upgrade stats");
+    schema["properties"]["byScriptType"] = json!({
+        "type": "object",
+        "additionalProperties": object(
+            "Script type totals",
+            json!({ "outpoints": quantity(), "amount": quantity() }),
+        ),
+    });
+    let mut totals = upgrade_totals("Block upgrade totals");
+    totals["properties"]["blockNumber"] = quantity();
+    totals["required"] = json!(["blockNumber", "upgraded", "outpoints", "recipients"]);
+    schema["properties"]["series"] = array(totals);
+    schema["required"] = json!([
+        "upgraded",
+        "outpoints",
+        "recipients",
+        "byScriptType",
+        "series"
+    ]);
+
+    schema
+}
+
+// Every method in `METHODS` needs an entry here, see the `documented` test
+fn documentation(name: &str) -> Option<(&'static str, Value)> {
+    Some(match name {
+        "rpc.discover" => (
+            "Returns this document",
+            object(
+                "OpenRPC document",
+                json!({
+                    "openrpc": string(),
+                    "info": object("Info", json!({ "title": string(), "version": string() })),
+                    "methods": array(json!({ "type": "object" })),
+                }),
+            ),
+        ),
+        "net_version" => ("Returns the network id", quantity()),
+        "net_listening" => ("Always true", boolean()),
+        "net_peerCount" => ("Always zero", quantity()),
+        "web3_clientVersion" => ("Returns the node's name and version", string()),
+        "web3_sha3" => ("Returns the keccak256 hash of the data", hash()),
+        "eth_blockNumber" => ("Returns the number of the latest block", quantity()),
+        "eth_call" => ("Always empty, there are no contracts to call", bytes()),
+        "eth_chainId" => ("Returns the chain id", quantity()),
+        "eth_estimateGas" => (
+            "Returns the intrinsic gas of a transaction, 21000 plus its calldata cost",
+            quantity(),
+        ),
+        "eth_gasPrice" => ("Gas is free", quantity()),
+        "eth_getBalance" => ("Returns the balance of an address", quantity()),
+        "eth_getBlockByHash" => ("Returns a block by hash", nullable(block())),
+        "eth_getBlockByNumber" => ("Returns a block by number", nullable(block())),
+        "eth_getBlockTransactionCountByHash" => (
+            "Returns the number of transactions in a block",
+            nullable(quantity()),
+        ),
+        "eth_getBlockTransactionCountByNumber" => (
+            "Returns the number of transactions in a block",
+            nullable(quantity()),
+        ),
+        "eth_getTransactionByBlockHashAndIndex" => (
+            "Returns a transaction by its position in a block",
+            nullable(transaction()),
+        ),
+        "eth_getTransactionByBlockNumberAndIndex" => (
+            "Returns a transaction by its position in a block",
+            nullable(transaction()),
+        ),
+        "eth_getBlockReceipts" => (
+            "Returns the receipts of every transaction in a block",
+            nullable(array(receipt())),
+        ),
+        "eth_feeHistory" => ("Fees are always zero", fee_history()),
+        "eth_accounts" => ("The node holds no keys", array(address())),
+        "eth_getCode" => ("Always empty, there are no contracts", bytes()),
+        "eth_getTransactionCount" => ("Returns the nonce of an address", quantity()),
+        "eth_getTransactionByHash" => ("Returns a transaction by hash", nullable(transaction())),
+        "eth_getTransactionReceipt" => (
+            "Returns a receipt, null until the transaction is in a block",
+            nullable(receipt()),
+        ),
+        "eth_sendRawTransaction" => ("Submits a signed transaction", hash()),
+        "eth_maxPriorityFeePerGas" => ("Gas is free", quantity()),
+        "eth_syncing" => (
+            "False unless a follower is catching up",
+            json!({
+                "oneOf": [
+                    boolean(),
+                    object(
+                        "Sync status",
+                        json!({
+                            "startingBlock": quantity(),
+                            "currentBlock": quantity(),
+                            "highestBlock": quantity(),
+                        }),
+                    ),
+                ],
+            }),
+        ),
+        "btc2_getLedger" => (
+            "Returns an address's newest ledger entries, or a page of them when options are given",
+            json!({
+                "oneOf": [
+                    array(ledger_entry()),
+                    object(
+                        "Ledger page",
+                        json!({
+                            "entries": array(ledger_entry()),
+                            "nextCursor": nullable(quantity()),
+                        }),
+                    ),
+                ],
+            }),
+        ),
+        "btc2_getAnchors" => ("Returns the Bitcoin anchors of the chain", array(anchor())),
+        "btc2_getSupply" => (
+            "Returns the legacy supply and how much of it has been upgraded",
+            object(
+                "Supply",
+                json!({
+                    "legacySupply": quantity(),
+                    "legacyUnclaimed": quantity(),
+                    "upgraded": quantity(),
+                }),
+            ),
+        ),
+        "btc2_getUpgradeStats" => (
+            "Returns upgrade totals by script type and by block",
+            upgrade_stats(),
+        ),
+        "btc2_buildUpgradeMessage" => (
+            "Builds the message and calldata to upgrade legacy outputs",
+            object(
+                "Upgrade message",
+                json!({
+                    "message": string(),
+                    "amount": quantity(),
+                    "to": address(),
+                    "data": bytes(),
+                    "signatureOffset": integer(),
+                }),
+            ),
+        ),
+        "btc2_getLegacyBalance" => (
+            "Returns the unclaimed legacy outputs of a Bitcoin address",
+            object(
+                "Legacy balance",
+                json!({
+                    "address": string(),
+                    "scriptType": script_type(),
+                    "claimable": boolean(),
+                    "outputs": array(object(
+                        "Legacy outpoint",
+                        json!({ "txid": txid(), "vout": integer(), "amount": quantity() }),
+                    )),
+                    "total": quantity(),
+                }),
+            ),
+        ),
+        "btc2_getLegacyOutput" => (
+            "Returns a legacy output from the snapshot",
+            nullable(object(
+                "Legacy output",
+                json!({
+                    "txid": txid(),
+                    "vout": integer(),
+                    "amount": quantity(),
+                    "scriptType": script_type(),
+                    "claimable": boolean(),
+                    "upgraded": boolean(),
+                    "upgradeTransactionHash": nullable(hash()),
+                }),
+            )),
+        ),
+        "btc2_getRawBlocks" => (
+            "Returns encoded blocks for followers",
+            array(object(
+                "Raw block",
+                json!({
+                    "number": quantity(),
+                    "hash": hash(),
+                    "timestamp": quantity(),
+                    "signature": nullable(bytes()),
+                    "transactions": array(bytes()),
+                }),
+            )),
+        ),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A titled object without properties says nothing about the result
+    fn assert_described(schema: &Value, path: &str) {
+        match schema {
+            Value::Object(schema) => {
+                if schema.get("type") == Some(&json!("object")) && schema.contains_key("title") {
+                    assert!(
+                        schema.contains_key("properties")
+                            || schema.contains_key("additionalProperties"),
+                        "{} has no properties",
+                        path
+                    );
+                }
+                for (key, value) in schema {
+                    assert_described(value, &format!("{}.{}", path, key));
+                }
+            }
+            Value::Array(schemas) => {
+                for (index, value) in schemas.iter().enumerate() {
+                    assert_described(value, &format!("{}[{}]", path, index));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    #[test]
+    fn documented() {
+        for method in METHODS {
+            let (summary, result) = documentation(method.name)
+                .unwrap_or_else(|| panic!("{} is undocumented", method.name));
+            assert!(!summary.is_empty());
+            assert_described(&result, method.name);
+        }
+        assert_eq!(
+            document()["methods"].as_array().unwrap().len(),
+            METHODS.len()
+        );
+    }
+}
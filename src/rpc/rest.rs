@@ -0,0 +1,267 @@
+use super::{
+    btc2::{reversed, DEFAULT_LEDGER_PAGE_SIZE, MAX_LEDGER_PAGE_SIZE},
+    encode_bytes, parse_bytes_like,
+};
+use crate::{
+    bitcoin_legacy::utxos,
+    chain_head::ChainHead,
+    db::{self, LedgerDirection, LedgerHistoryEntry, LedgerQuery, StoredTransaction},
+    error::{Error, Result},
+    evm::scale_up,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use reth_primitives::{keccak256, Address, U256};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::task::spawn_blocking;
+
+// Read only views of the chain for explorers and scripts. Quantities are plain
+// numbers, and values that can overflow a JSON number are decimal strings.
+
+pub async fn block(
+    State(pool): State<PgPool>,
+    State(chain_head): State<ChainHead>,
+    Path(number): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&headers, block_json(&pool, &chain_head, &number).await)
+}
+
+pub async fn transaction(
+    State(pool): State<PgPool>,
+    Path(hash): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&headers, transaction_json(&pool, &hash).await)
+}
+
+pub async fn address(
+    State(pool): State<PgPool>,
+    Path(address): Path<String>,
+    Query(options): Query<LedgerOptions>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&headers, address_json(&pool, &address, options).await)
+}
+
+pub async fn legacy_output(
+    State(pool): State<PgPool>,
+    Path((txid, vout)): Path<(String, u16)>,
+    headers: HeaderMap,
+) -> Response {
+    respond(&headers, legacy_output_json(&pool, &txid, vout).await)
+}
+
+async fn block_json(pool: &PgPool, chain_head: &ChainHead, number: &str) -> Result<Option<Value>> {
+    let block = match number {
+        "latest" => chain_head.block(),
+        number => {
+            let number = number
+                .parse()
+                .map_err(|_| Error::ParseError(format!("Invalid block number {}", number)))?;
+            db::get_block_by_number(pool, number).await?
+        }
+    };
+    let Some(block) = block else {
+        return Ok(None);
+    };
+    let parent_hash = db::get_head_block(pool, block.number - 1)
+        .await?
+        .map(|block| block.hash)
+        .unwrap_or_default();
+    let transactions = db::get_stored_transactions_by_block_number(pool, block.number).await?;
+
+    Ok(Some(json!({
+        "number": block.number,
+        "hash": encode_bytes(&block.hash),
+        "parentHash": encode_bytes(&parent_hash),
+        "timestamp": block.timestamp.assume_utc().unix_timestamp(),
+        "transactions": transactions.iter().map(transaction_object).collect::<Vec<Value>>(),
+    })))
+}
+
+async fn transaction_json(pool: &PgPool, hash: &str) -> Result<Option<Value>> {
+    let hash: [u8; 32] = parse_bytes_like(hash)?
+        .try_into()
+        .map_err(|_| Error::ParseError("Expected 32 byte hash".to_string()))?;
+
+    Ok(db::get_stored_transaction_by_hash(pool, hash)
+        .await?
+        .as_ref()
+        .map(transaction_object))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LedgerOptions {
+    cursor: Option<i64>,
+    limit: Option<i64>,
+    from_block: Option<i64>,
+    to_block: Option<i64>,
+    direction: Option<LedgerDirection>,
+    // Newest first unless `asc`
+    order: Option<String>,
+}
+
+async fn address_json(
+    pool: &PgPool,
+    address: &str,
+    options: LedgerOptions,
+) -> Result<Option<Value>> {
+    let address: [u8; 20] = parse_bytes_like(address)?
+        .try_into()
+        .map_err(|_| Error::ParseError("Expected 20 byte address".to_string()))?;
+    let limit = options
+        .limit
+        .unwrap_or(DEFAULT_LEDGER_PAGE_SIZE)
+        .clamp(1, MAX_LEDGER_PAGE_SIZE);
+    let entries = db::get_ledger_history(
+        pool,
+        &LedgerQuery {
+            address,
+            cursor: options.cursor,
+            limit: Some(limit),
+            from_block: options.from_block,
+            to_block: options.to_block,
+            direction: options.direction,
+            descending: options.order.as_deref() != Some("asc"),
+        },
+    )
+    .await?;
+    let next_cursor = match entries.last() {
+        Some(entry) if entries.len() as i64 == limit => Some(entry.id),
+        _ => None,
+    };
+    let balance = db::get_balance_or_zero(pool, address).await?;
+    let nonce = db::get_transaction_count_by_address(pool, address).await?;
+
+    Ok(Some(json!({
+        "address": Address::from(address).to_checksum(None),
+        "balance": scale_up(balance).to_string(),
+        "nonce": nonce,
+        "ledger": {
+            "entries": entries
+                .iter()
+                .map(|entry| ledger_entry(address, entry))
+                .collect::<Vec<Value>>(),
+            "nextCursor": next_cursor,
+        },
+    })))
+}
+
+async fn legacy_output_json(pool: &PgPool, txid: &str, vout: u16) -> Result<Option<Value>> {
+    let txid = super::btc2::parse_txid(txid)?;
+    let Some(output) = spawn_blocking(move || {
+        utxos::get(&utxos::Outpoint {
+            hash: reversed(txid),
+            index: vout,
+        })
+    })
+    .await??
+    else {
+        return Ok(None);
+    };
+    let spent = db::get_spent_legacy_output(pool, txid, i16::try_from(vout)?).await?;
+
+    Ok(Some(json!({
+        "txid": hex::encode(txid),
+        "vout": vout,
+        "amount": output.amount,
+        "scriptType": output.script_type.name(),
+        "claimable": output.script_type.claimable(),
+        "upgraded": spent.is_some(),
+        "upgradeTransactionHash": spent
+            .and_then(|spent| spent.transaction_hash)
+            .map(|hash| encode_bytes(&hash)),
+    })))
+}
+
+// The RPC objects with quantities as plain numbers, and as decimal strings
+// where they can overflow a JSON number
+fn rest_encoding(mut object: Value, numbers: &[&str], decimals: &[&str]) -> Value {
+    let parse = |quantity: &Value| -> Option<U256> {
+        U256::from_str_radix(quantity.as_str()?.strip_prefix("0x")?, 16).ok()
+    };
+    for key in numbers {
+        if let Some(quantity) =
+            parse(&object[key]).and_then(|quantity| u64::try_from(quantity).ok())
+        {
+            object[key] = json!(quantity);
+        }
+    }
+    for key in decimals {
+        if let Some(quantity) = parse(&object[key]) {
+            object[key] = json!(quantity.to_string());
+        }
+    }
+    object
+}
+
+fn transaction_object(stored: &StoredTransaction) -> Value {
+    rest_encoding(
+        super::eth::transaction_object(stored),
+        &[
+            "blockNumber",
+            "transactionIndex",
+            "nonce",
+            "gas",
+            "chainId",
+            "v",
+        ],
+        &["gasPrice", "value"],
+    )
+}
+
+fn ledger_entry(address: [u8; 20], entry: &LedgerHistoryEntry) -> Value {
+    rest_encoding(
+        super::btc2::ledger_entry(address, entry),
+        &["id", "blockNumber", "timestamp"],
+        &["value"],
+    )
+}
+
+// The ETag is a hash of the body so any change to the resource changes it
+fn respond(headers: &HeaderMap, result: Result<Option<Value>>) -> Response {
+    let body = match result {
+        Ok(Some(body)) => body.to_string(),
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Json(json!({ "error": "Not found" }))).into_response()
+        }
+        Err(err) => {
+            let status = match err {
+                Error::ParseError(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            return (status, Json(json!({ "error": err.to_string() }))).into_response();
+        }
+    };
+    let etag = format!("\"{}\"", hex::encode(&keccak256(&body)[..16]));
+    let cache_headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, "no-cache".to_string()),
+    ];
+
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag))
+    {
+        return (StatusCode::NOT_MODIFIED, cache_headers).into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            cache_headers[0].clone(),
+            cache_headers[1].clone(),
+        ],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,18 @@
+use super::{eth::encode_bytes, ResponseValue};
+use crate::error::Result;
+
+use reth_primitives::keccak256;
+use serde_json::json;
+
+pub async fn client_version() -> Result<ResponseValue> {
+    Ok(ResponseValue::Value(json!(format!(
+        "bitcoin2/v{}",
+        env!("CARGO_PKG_VERSION")
+    ))))
+}
+
+pub async fn sha3(data: Vec<u8>) -> Result<ResponseValue> {
+    Ok(ResponseValue::Value(encode_bytes(
+        keccak256(data).as_slice(),
+    )))
+}
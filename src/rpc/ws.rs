@@ -0,0 +1,351 @@
+use super::{eth::block_header, handle_request, respond, JsonRpcRequest, ParamValue};
+use crate::{
+    error::{Error, Result},
+    events::{self, Event},
+    metrics,
+    rate_limit::{self, Client},
+    AppState,
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, State,
+    },
+    http::HeaderMap,
+    response::Response,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::sync::broadcast::error::RecvError;
+
+static NEXT_SUBSCRIPTION_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, PartialEq)]
+enum Subscription {
+    NewHeads,
+    NewPendingTransactions,
+    // Plain transfers and upgrades emit no logs, so these never fire
+    Logs,
+}
+
+impl TryFrom<&[Value]> for Subscription {
+    type Error = Error;
+
+    fn try_from(params: &[Value]) -> Result<Self> {
+        match params {
+            [Value::String(kind), ..] if kind == "newHeads" => Ok(Self::NewHeads),
+            [Value::String(kind), ..] if kind == "newPendingTransactions" => {
+                Ok(Self::NewPendingTransactions)
+            }
+            [Value::String(kind)] if kind == "logs" => Ok(Self::Logs),
+            [Value::String(kind), filter] if kind == "logs" => {
+                check_log_filter(filter)?;
+                Ok(Self::Logs)
+            }
+            [Value::String(kind), ..] => Err(Error::ParseError(format!(
+                "Unsupported subscription {}",
+                kind
+            ))),
+            _ => Err(Error::ParseError(
+                "Invalid params for eth_subscribe".to_string(),
+            )),
+        }
+    }
+}
+
+// Same shape as an eth_getLogs filter, without the block range
+fn check_log_filter(filter: &Value) -> Result<()> {
+    let Value::Object(filter) = filter else {
+        return Err(Error::ParseError("Expected a log filter".to_string()));
+    };
+    for (key, value) in filter {
+        match (key.as_str(), value) {
+            ("address", Value::Array(addresses)) => {
+                for address in addresses {
+                    <[u8; 20]>::try_from(&ParamValue(address.clone()))?;
+                }
+            }
+            ("address", address) => {
+                <[u8; 20]>::try_from(&ParamValue(address.clone()))?;
+            }
+            ("topics", Value::Array(topics)) if topics.len() <= 4 => {
+                for topic in topics {
+                    match topic {
+                        Value::Null => {}
+                        Value::Array(alternatives) => {
+                            for alternative in alternatives {
+                                <[u8; 32]>::try_from(&ParamValue(alternative.clone()))?;
+                            }
+                        }
+                        topic => {
+                            <[u8; 32]>::try_from(&ParamValue(topic.clone()))?;
+                        }
+                    }
+                }
+            }
+            ("topics", _) => {
+                return Err(Error::ParseError("Expected at most 4 topics".to_string()))
+            }
+            (key, _) => {
+                return Err(Error::ParseError(format!(
+                    "Unsupported log filter field {}",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Response {
+    let client = Client::new(connect_info, &headers);
+    ws.on_upgrade(move |socket| serve(socket, state, client))
+}
+
+async fn serve(mut socket: WebSocket, state: AppState, client: Option<Client>) {
+    let mut events = events::subscribe();
+    // Locked only between awaits, requests in a batch run one after another
+    let subscriptions: Mutex<HashMap<String, Subscription>> = Mutex::new(HashMap::new());
+
+    loop {
+        let outgoing = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    handle_message(&state, client.as_ref(), &text, &subscriptions)
+                        .await
+                        .into_iter()
+                        .collect()
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+            event = events.recv() => match event {
+                Ok(event) => notifications(&subscriptions.lock().unwrap(), &event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+        };
+
+        for message in outgoing {
+            if socket
+                .send(Message::Text(message.to_string()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+// A single request or a batch, handled like the HTTP transport
+async fn handle_message(
+    state: &AppState,
+    client: Option<&Client>,
+    text: &str,
+    subscriptions: &Mutex<HashMap<String, Subscription>>,
+) -> Option<Value> {
+    let body: Value = match serde_json::from_str(text)
+        .map_err(|e| Error::InvalidJson(e.to_string()))
+        .inspect_err(metrics::record_error)
+    {
+        Ok(body) => body,
+        Err(err) => return Some(err.to_json_rpc(Value::Null)),
+    };
+
+    respond(body, |request| {
+        handle_ws_request(state, client, subscriptions, request)
+    })
+    .await
+    .unwrap_or_else(|err| Some(err.to_json_rpc(Value::Null)))
+}
+
+async fn handle_ws_request(
+    state: &AppState,
+    client: Option<&Client>,
+    subscriptions: &Mutex<HashMap<String, Subscription>>,
+    request: JsonRpcRequest,
+) -> Result<Value> {
+    if let Some(client) = client {
+        rate_limit::check(client, rate_limit::cost(&request.method))?;
+    }
+    let id = request.id.clone().unwrap_or_default();
+    let params = match &request.params {
+        Some(Value::Array(params)) => params.clone(),
+        _ => vec![],
+    };
+    match (request.method.as_str(), &params[..]) {
+        ("eth_subscribe", params) => {
+            let subscription = Subscription::try_from(params)?;
+            let subscription_id = format!(
+                "0x{:x}",
+                NEXT_SUBSCRIPTION_ID.fetch_add(1, Ordering::Relaxed)
+            );
+            subscriptions
+                .lock()
+                .unwrap()
+                .insert(subscription_id.clone(), subscription);
+            Ok(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": subscription_id,
+            }))
+        }
+        ("eth_unsubscribe", [Value::String(subscription_id)]) => Ok(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": subscriptions.lock().unwrap().remove(subscription_id).is_some(),
+        })),
+        ("eth_unsubscribe", _) => Err(Error::ParseError(
+            "Invalid params for eth_unsubscribe".to_string(),
+        )),
+        _ => handle_request(state.clone(), request).await,
+    }
+}
+
+fn notifications(subscriptions: &HashMap<String, Subscription>, event: &Event) -> Vec<Value> {
+    let (kind, result) = match event {
+        Event::NewHead { block, parent_hash } => {
+            (Subscription::NewHeads, block_header(block, *parent_hash))
+        }
+        Event::PendingTransaction(hash) => (
+            Subscription::NewPendingTransactions,
+            json!(format!("0x{}", hex::encode(hash))),
+        ),
+    };
+
+    subscriptions
+        .iter()
+        .filter(|(_, subscription)| **subscription == kind)
+        .map(|(subscription_id, _)| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "eth_subscription",
+                "params": {
+                    "subscription": subscription_id,
+                    "result": result,
+                },
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{app, constants::LAST_LEGACY_BLOCK_TIMESTAMP, db::Block, events};
+    use futures_util::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+    type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn send(socket: &mut Socket, id: u64, method: &str, params: Value) {
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        socket
+            .send(Message::Text(request.to_string()))
+            .await
+            .unwrap();
+    }
+
+    // Skips anything else, other tests publish to the same event bus
+    async fn receive(socket: &mut Socket, matches: impl Fn(&Value) -> bool) -> Value {
+        loop {
+            let Message::Text(text) = socket.next().await.unwrap().unwrap() else {
+                continue;
+            };
+            let message: Value = serde_json::from_str(&text).unwrap();
+            if matches(&message) {
+                return message;
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn subscribe(pool: PgPool) -> sqlx::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let app = app(pool.clone()).await;
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let (mut socket, _) = connect_async(url).await.unwrap();
+
+        send(&mut socket, 1, "eth_subscribe", json!(["newHeads"])).await;
+        let subscription_id =
+            receive(&mut socket, |message| message["id"] == 1).await["result"].clone();
+        assert!(subscription_id.is_string());
+
+        send(
+            &mut socket,
+            2,
+            "eth_subscribe",
+            json!(["logs", {"address": format!("0x{}", hex::encode([1; 20]))}]),
+        )
+        .await;
+        let response = receive(&mut socket, |message| message["id"] == 2).await;
+        assert!(response["result"].is_string());
+        send(
+            &mut socket,
+            5,
+            "eth_subscribe",
+            json!(["logs", {"topics": [1]}]),
+        )
+        .await;
+        let response = receive(&mut socket, |message| message["id"] == 5).await;
+        assert_eq!(response["error"]["code"], -32602);
+
+        // The notification gets no response, so the batch answers only id 6
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "eth_subscribe", "params": ["newPendingTransactions"]},
+            {"jsonrpc": "2.0", "id": 6, "method": "eth_subscribe", "params": ["newPendingTransactions"]},
+        ]);
+        socket.send(Message::Text(batch.to_string())).await.unwrap();
+        let response = receive(&mut socket, Value::is_array).await;
+        assert_eq!(response.as_array().unwrap().len(), 1);
+        assert_eq!(response[0]["id"], 6);
+        assert!(response[0]["result"].is_string());
+
+        events::publish(events::Event::NewHead {
+            block: Block {
+                number: 840000,
+                hash: [7; 32],
+                timestamp: LAST_LEGACY_BLOCK_TIMESTAMP,
+                signature: None,
+            },
+            parent_hash: [6; 32],
+        });
+        let hash = format!("0x{}", hex::encode([7; 32]));
+        let notification = receive(&mut socket, |message| {
+            message["params"]["result"]["hash"] == hash.as_str()
+        })
+        .await;
+        assert_eq!(notification["method"], "eth_subscription");
+        assert_eq!(notification["params"]["subscription"], subscription_id);
+        assert_eq!(notification["params"]["result"]["number"], "0xcd140");
+
+        send(&mut socket, 3, "eth_unsubscribe", json!([subscription_id])).await;
+        assert_eq!(
+            receive(&mut socket, |message| message["id"] == 3).await["result"],
+            true
+        );
+        send(&mut socket, 4, "eth_unsubscribe", json!([subscription_id])).await;
+        assert_eq!(
+            receive(&mut socket, |message| message["id"] == 4).await["result"],
+            false
+        );
+
+        Ok(())
+    }
+}
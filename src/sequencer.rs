@@ -0,0 +1,60 @@
+use crate::{
+    constants::SEQUENCER_KEY,
+    error::{Error, Result},
+};
+use k256::ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey};
+
+// Signatures are laid out as r || s || recovery id
+
+pub fn sign(block_hash: &[u8; 32]) -> Result<Option<[u8; 65]>> {
+    SEQUENCER_KEY
+        .as_ref()
+        .map(|signing_key| sign_with(signing_key, block_hash))
+        .transpose()
+}
+
+pub fn sign_with(signing_key: &SigningKey, block_hash: &[u8; 32]) -> Result<[u8; 65]> {
+    let (signature, recovery_id) = signing_key
+        .sign_prehash_recoverable(block_hash)
+        .map_err(|e| Error::Error(e.to_string()))?;
+
+    Ok([&signature.to_bytes()[..], &[recovery_id.to_byte()]]
+        .concat()
+        .try_into()?)
+}
+
+pub fn recover(block_hash: &[u8; 32], signature: &[u8; 65]) -> Result<VerifyingKey> {
+    VerifyingKey::recover_from_prehash(
+        block_hash,
+        &Signature::from_slice(&signature[..64]).map_err(|_| Error::InvalidSignature)?,
+        RecoveryId::from_byte(signature[64]).ok_or(Error::InvalidSignature)?,
+    )
+    .map_err(|_| Error::InvalidSignature)
+}
+
+pub fn verify(
+    expected: &VerifyingKey,
+    block_hash: &[u8; 32],
+    signature: Option<[u8; 65]>,
+) -> Result<()> {
+    match signature {
+        Some(signature) if recover(block_hash, &signature)? == *expected => Ok(()),
+        _ => Err(Error::InvalidSignature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use k256::ecdsa::SigningKey;
+
+    #[test]
+    fn recover() {
+        let signing_key = SigningKey::from_slice(&[1; 32]).unwrap();
+        let signature = super::sign_with(&signing_key, &[2; 32]).unwrap();
+
+        assert_eq!(
+            super::recover(&[2; 32], &signature).unwrap(),
+            *signing_key.verifying_key()
+        );
+    }
+}
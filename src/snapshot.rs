@@ -0,0 +1,343 @@
+use crate::{
+    constants::{CHAIN_ID, LAST_LEGACY_BLOCK_NUMBER},
+    db::{
+        get_account_states_at, get_head_block, get_spent_legacy_outputs_at, get_transaction_count,
+        import_account_state, import_block, import_spent_legacy_output, insert_imported_snapshot,
+        AccountState, Block, SpentLegacyOutput,
+    },
+    error::{Error, Result},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{query, types::time::PrimitiveDateTime, PgPool};
+use std::path::Path;
+
+pub const SNAPSHOT_FORMAT: &str = "bitcoin2-snapshot";
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub format: String,
+    pub version: u32,
+    pub chain_id: i64,
+    pub height: i64,
+    // `None` when the snapshot is taken before the first BTC2 block
+    pub head: Option<SnapshotBlock>,
+    pub accounts: Vec<SnapshotAccount>,
+    // The EVM has no persistent contract storage yet so this is always empty
+    pub contract_state: Vec<SnapshotContract>,
+    pub spent_legacy_outputs: Vec<SnapshotOutpoint>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotBlock {
+    pub number: i64,
+    #[serde(with = "hex::serde")]
+    pub hash: [u8; 32],
+    pub timestamp: PrimitiveDateTime,
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotAccount {
+    #[serde(with = "hex::serde")]
+    pub address: [u8; 20],
+    pub balance: i64,
+    pub nonce: i64,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotContract {
+    #[serde(with = "hex::serde")]
+    pub address: [u8; 20],
+    #[serde(with = "hex::serde")]
+    pub code: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotOutpoint {
+    #[serde(with = "hex::serde")]
+    pub hash: [u8; 32],
+    pub index: i16,
+    #[serde(with = "optional_hex")]
+    pub transaction_hash: Option<[u8; 32]>,
+}
+
+// `hex::serde` for values that can be null
+mod optional_hex {
+    use hex::FromHex;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<[u8; 32]>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.map(hex::encode).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<[u8; 32]>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| <[u8; 32]>::from_hex(value).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub snapshot: Snapshot,
+    // Hex encoded sha256 of the JSON encoded `snapshot`
+    pub checksum: String,
+}
+
+impl Snapshot {
+    pub fn checksum(&self) -> Result<String> {
+        let bytes = serde_json::to_vec(self).map_err(|e| Error::Error(e.to_string()))?;
+        Ok(hex::encode(Sha256::digest(bytes)))
+    }
+}
+
+impl SnapshotFile {
+    pub fn new(snapshot: Snapshot) -> Result<Self> {
+        Ok(Self {
+            checksum: snapshot.checksum()?,
+            snapshot,
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let file: Self = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| Error::ParseError(e.to_string()))?;
+        if file.snapshot.format != SNAPSHOT_FORMAT || file.snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::Error(format!(
+                "Unsupported snapshot {} version {}",
+                file.snapshot.format, file.snapshot.version
+            )));
+        }
+        if file.snapshot.checksum()? != file.checksum {
+            return Err(Error::Error("Snapshot checksum mismatch".to_string()));
+        }
+        Ok(file)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(self).map_err(|e| Error::Error(e.to_string()))?;
+        Ok(std::fs::write(path, bytes)?)
+    }
+}
+
+// Defaults to the latest block when no height is given
+pub async fn export(pool: &PgPool, height: Option<i64>) -> Result<Snapshot> {
+    // Every query sees the same state, even while blocks are being produced
+    let mut tx = pool.begin().await?;
+    query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+    let head = get_head_block(&mut *tx, height.unwrap_or(i64::MAX)).await?;
+    let height = match (height, &head) {
+        (Some(height), Some(head)) if head.number != height => {
+            return Err(Error::Error(format!("Block {} not found", height)))
+        }
+        (Some(height), None) if height != LAST_LEGACY_BLOCK_NUMBER => {
+            return Err(Error::Error(format!("Block {} not found", height)))
+        }
+        (_, Some(head)) => head.number,
+        (_, None) => LAST_LEGACY_BLOCK_NUMBER,
+    };
+
+    let snapshot = Snapshot {
+        format: SNAPSHOT_FORMAT.to_string(),
+        version: SNAPSHOT_VERSION,
+        chain_id: CHAIN_ID,
+        height,
+        head: head.map(|block| SnapshotBlock {
+            number: block.number,
+            hash: block.hash,
+            timestamp: block.timestamp,
+            signature: block.signature.map(hex::encode),
+        }),
+        accounts: get_account_states_at(&mut *tx, height)
+            .await?
+            .into_iter()
+            .map(|account| SnapshotAccount {
+                address: account.address,
+                balance: account.balance,
+                nonce: account.nonce,
+            })
+            .collect(),
+        contract_state: vec![],
+        spent_legacy_outputs: get_spent_legacy_outputs_at(&mut *tx, height)
+            .await?
+            .into_iter()
+            .map(|output| SnapshotOutpoint {
+                hash: output.hash,
+                index: output.index,
+                transaction_hash: output.transaction_hash,
+            })
+            .collect(),
+    };
+    tx.commit().await?;
+
+    Ok(snapshot)
+}
+
+// Bootstraps a freshly migrated database from a snapshot
+pub async fn import(pool: &PgPool, snapshot: &Snapshot) -> Result<()> {
+    if snapshot.chain_id != CHAIN_ID {
+        return Err(Error::Error(format!(
+            "Snapshot is for chain {} not {}",
+            snapshot.chain_id, CHAIN_ID
+        )));
+    }
+    if !snapshot.contract_state.is_empty() {
+        return Err(Error::Error(
+            "Contract state is not supported by this node".to_string(),
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+    if get_transaction_count(&mut *tx).await? != 0
+        || get_head_block(&mut *tx, i64::MAX).await?.is_some()
+    {
+        return Err(Error::Error(
+            "Snapshots can only be imported into an empty database".to_string(),
+        ));
+    }
+
+    for account in &snapshot.accounts {
+        import_account_state(
+            &mut *tx,
+            &AccountState {
+                address: account.address,
+                balance: account.balance,
+                nonce: account.nonce,
+            },
+        )
+        .await?;
+    }
+    for output in &snapshot.spent_legacy_outputs {
+        import_spent_legacy_output(
+            &mut *tx,
+            &SpentLegacyOutput {
+                hash: output.hash,
+                index: output.index,
+                transaction_hash: output.transaction_hash,
+            },
+        )
+        .await?;
+    }
+    if let Some(head) = &snapshot.head {
+        import_block(
+            &mut *tx,
+            &Block {
+                number: head.number,
+                hash: head.hash,
+                timestamp: head.timestamp,
+                signature: head
+                    .signature
+                    .as_ref()
+                    .map(|signature| -> Result<[u8; 65]> {
+                        Ok(hex::decode(signature)?.try_into()?)
+                    })
+                    .transpose()?,
+            },
+        )
+        .await?;
+    }
+    // `verify::verify_chain` replays from here instead of the legacy seed
+    insert_imported_snapshot(&mut *tx, snapshot.height).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::{LEGACY_ACCOUNT, LEGACY_SUPPLY, MIGRATOR},
+        db::{
+            get_balance, get_transaction_count_by_address, insert_block,
+            update_transactions_block_number,
+        },
+        evm::{Evm, TransactionSigned},
+    };
+    use sqlx::{postgres::PgPoolOptions, query_as, PgPool};
+
+    fn decode(raw_transaction: &str) -> TransactionSigned {
+        TransactionSigned::decode_rlp_legacy_transaction(
+            &mut &hex::decode(raw_transaction).unwrap()[..],
+        )
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn export_and_import(pool: PgPool) -> sqlx::Result<()> {
+        let snapshot = export(&pool, None).await.unwrap();
+        assert_eq!(snapshot.height, LAST_LEGACY_BLOCK_NUMBER);
+        assert_eq!(snapshot.accounts[0].balance, LEGACY_SUPPLY);
+
+        let file = SnapshotFile::new(snapshot).unwrap();
+        let path = tempfile::NamedTempFile::new().unwrap();
+        file.write(path.path()).unwrap();
+        let read = SnapshotFile::read(path.path()).unwrap();
+        assert_eq!(read.snapshot, file.snapshot);
+
+        import(&pool, &read.snapshot).await.unwrap();
+        assert_eq!(
+            get_balance(&pool, LEGACY_ACCOUNT).await.unwrap(),
+            LEGACY_SUPPLY
+        );
+
+        Ok(())
+    }
+    #[sqlx::test]
+    async fn export_and_import_into_fresh_database(pool: PgPool) -> sqlx::Result<()> {
+        let address = hex_lit::hex!("f204ee5596cabc6ec60e5e92fd412ea7f856b625");
+        let evm = Evm::new(pool.clone());
+        evm.deposit(address, 100000000).await;
+        let transfer = evm.run_transaction(&decode("f8698080825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b2000080820188a0db848c751522df8fb1d9c317f344b40251bc73c6db7a4d8dfadf929f1c21e21aa01a4203287ae5b0a3f1c98e79e08b49cc5dafd6d96e5845b6d403250e1461a851")).await.unwrap();
+        let upgrade = evm.run_transaction(&decode("f90227068082520894000000000000000000000000000000000000000080b901c4e60b060d0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000014000000000000000000000000000000000000000000000000000000000000000cd416374696f6e3a20557067726164650a44657374696e6174696f6e20436861696e2049443a203230330a44657374696e6174696f6e20416464726573733a203078663230344545353539364341626336456336306535653932466434313245413766383536623632350a496e707574733a0a20202d0a20202020486173683a20343931363865626338323661383263633834633031333936363064396261666239313961366135316432663031626633313632393839363036316533393464300a20202020496e6465783a203000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004120239ff874c5e9bcdccf84c0e68333d0337f43847d54d56a3b1e339b4b59a975fb59d0741f8857b9467d00ca418d40f3a7f02e4fa4cbd68daf5c29163310724fdf00000000000000000000000000000000000000000000000000000000000000820188a0af7479b422eefc7e6f3051922e63ceb5330fdd08da66c22d5619f2ba514e24faa01ff98a79df5b2b48007e99e810e0d1d6aeed931993401880efef5dae7f46d790")).await.unwrap();
+        let block = insert_block(&pool, [1; 32], None).await.unwrap();
+        update_transactions_block_number(&pool, vec![transfer, upgrade], block.number)
+            .await
+            .unwrap();
+        // Still pending, so it is left out of the snapshot
+        evm.run_transaction(&decode("f8690180825208943073ac44aa1b95f2fe71bb2eb36b9ce27892f8ee8806f05b59d3b20000808201b9a0d95066012c1af3689ac24030b965a81211b506022d4db117bf90b4a22ccaf981a03c818c75f0634ee921cbcb290371c5e14e76768db4f18900753dbcce651978eb")).await.unwrap();
+
+        let snapshot = export(&pool, None).await.unwrap();
+        assert_eq!(snapshot.height, block.number);
+        assert_eq!(snapshot.spent_legacy_outputs.len(), 1);
+
+        let (database,) = query_as::<_, (String,)>("SELECT current_database() || '_import'")
+            .fetch_one(&pool)
+            .await?;
+        sqlx::query(&format!("CREATE DATABASE {database}"))
+            .execute(&pool)
+            .await?;
+        let fresh = PgPoolOptions::new()
+            .connect_with((*pool.connect_options()).clone().database(&database))
+            .await?;
+        MIGRATOR.run(&fresh).await.unwrap();
+        import(&fresh, &snapshot).await.unwrap();
+
+        assert_eq!(export(&fresh, None).await.unwrap(), snapshot);
+        assert_eq!(
+            get_balance(&fresh, address).await.unwrap(),
+            100000000 - 50000000 + 136265
+        );
+        assert_eq!(
+            get_transaction_count_by_address(&fresh, address)
+                .await
+                .unwrap(),
+            2
+        );
+        fresh.close().await;
+        sqlx::query(&format!("DROP DATABASE {database}"))
+            .execute(&pool)
+            .await?;
+
+        Ok(())
+    }
+}
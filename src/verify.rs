@@ -0,0 +1,390 @@
+use crate::{
+    block_producer::block_hash,
+    constants::{LAST_LEGACY_BLOCK_NUMBER, LEGACY_ACCOUNT, LEGACY_SUPPLY, SYSTEM_ADDRESS},
+    db::{
+        get_balances, get_blocks_after, get_imported_balances, get_imported_height,
+        get_ledger_by_transaction_id, get_pending_stored_transactions,
+        get_spent_legacy_outputs_by_transaction_id, get_stored_transactions_by_block_number,
+        LedgerEntry, StoredTransaction,
+    },
+    error::Result,
+    evm::{scale_down, upgrade_by_message::Outpoint, validate_upgrade_by_message, Address},
+};
+use sqlx::PgPool;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+};
+
+#[derive(Debug)]
+pub enum Divergence {
+    BlockHash {
+        number: i64,
+        stored: [u8; 32],
+        computed: [u8; 32],
+    },
+    TransactionHash {
+        id: i64,
+        stored: [u8; 32],
+        computed: [u8; 32],
+    },
+    Signature {
+        hash: [u8; 32],
+        stored: [u8; 20],
+        recovered: Option<[u8; 20]>,
+    },
+    Upgrade {
+        hash: [u8; 32],
+        reason: String,
+    },
+    Ledger {
+        hash: [u8; 32],
+        expected: Vec<LedgerEntry>,
+        stored: Vec<LedgerEntry>,
+    },
+    SpentLegacyOutputs {
+        hash: [u8; 32],
+        expected: Vec<Outpoint>,
+        stored: Vec<Outpoint>,
+    },
+    Balance {
+        address: [u8; 20],
+        expected: i64,
+        stored: i64,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BlockHash {
+                number,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "block {} has hash 0x{} but its transactions hash to 0x{}",
+                number,
+                hex::encode(stored),
+                hex::encode(computed)
+            ),
+            Self::TransactionHash {
+                id,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "transaction {} has hash 0x{} but its signed bytes hash to 0x{}",
+                id,
+                hex::encode(stored),
+                hex::encode(computed)
+            ),
+            Self::Signature {
+                hash,
+                stored,
+                recovered,
+            } => write!(
+                f,
+                "transaction 0x{} is stored as sent by 0x{} but its signature recovers to {}",
+                hex::encode(hash),
+                hex::encode(stored),
+                recovered
+                    .map(|signer| format!("0x{}", hex::encode(signer)))
+                    .unwrap_or("nothing".to_string())
+            ),
+            Self::Upgrade { hash, reason } => write!(
+                f,
+                "upgrade 0x{} no longer validates: {}",
+                hex::encode(hash),
+                reason
+            ),
+            Self::Ledger {
+                hash,
+                expected,
+                stored,
+            } => write!(
+                f,
+                "transaction 0x{} should produce ledger entries {:?} but has {:?}",
+                hex::encode(hash),
+                expected,
+                stored
+            ),
+            Self::SpentLegacyOutputs {
+                hash,
+                expected,
+                stored,
+            } => write!(
+                f,
+                "transaction 0x{} should spend legacy outputs {:?} but spent {:?}",
+                hex::encode(hash),
+                expected,
+                stored
+            ),
+            Self::Balance {
+                address,
+                expected,
+                stored,
+            } => write!(
+                f,
+                "account 0x{} should have a balance of {} but has {}",
+                hex::encode(address),
+                expected,
+                stored
+            ),
+        }
+    }
+}
+
+// Re-derives the chain from the legacy seed, or from the snapshot the database was
+// imported from, and reports the first place the database disagrees with it
+pub async fn verify_chain(pool: &PgPool) -> Result<Option<Divergence>> {
+    // Blocks up to an imported snapshot's height have no stored transactions
+    let (height, mut balances): (i64, HashMap<[u8; 20], i64>) =
+        match get_imported_height(pool).await? {
+            Some(height) => (
+                height,
+                get_imported_balances(pool).await?.into_iter().collect(),
+            ),
+            None => (
+                LAST_LEGACY_BLOCK_NUMBER,
+                HashMap::from([(LEGACY_ACCOUNT, LEGACY_SUPPLY)]),
+            ),
+        };
+
+    // In chain order so the divergence reported is the earliest one, pending
+    // transactions come after every block
+    for block in get_blocks_after(pool, height, None).await? {
+        let transactions = get_stored_transactions_by_block_number(pool, block.number).await?;
+        let computed = block_hash(
+            transactions
+                .iter()
+                .map(|stored| stored.transaction.clone())
+                .collect(),
+        );
+        if computed != block.hash {
+            return Ok(Some(Divergence::BlockHash {
+                number: block.number,
+                stored: block.hash,
+                computed,
+            }));
+        }
+        for stored in &transactions {
+            if let Some(divergence) = verify_transaction(pool, stored, &mut balances).await? {
+                return Ok(Some(divergence));
+            }
+        }
+    }
+    for stored in get_pending_stored_transactions(pool).await? {
+        if let Some(divergence) = verify_transaction(pool, &stored, &mut balances).await? {
+            return Ok(Some(divergence));
+        }
+    }
+
+    // Accounts missing from either side count as a zero balance
+    let stored_balances: HashMap<[u8; 20], i64> = get_balances(pool).await?.into_iter().collect();
+    let addresses: BTreeSet<[u8; 20]> = stored_balances
+        .keys()
+        .chain(balances.keys())
+        .copied()
+        .collect();
+    for address in addresses {
+        let expected = balances.get(&address).cloned().unwrap_or(0);
+        let stored = stored_balances.get(&address).cloned().unwrap_or(0);
+        if expected != stored {
+            return Ok(Some(Divergence::Balance {
+                address,
+                expected,
+                stored,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+async fn verify_transaction(
+    pool: &PgPool,
+    StoredTransaction {
+        id,
+        hash,
+        signer,
+        transaction,
+        ..
+    }: &StoredTransaction,
+    balances: &mut HashMap<[u8; 20], i64>,
+) -> Result<Option<Divergence>> {
+    let (hash, signer) = (*hash, *signer);
+    let computed: [u8; 32] = transaction.hash().into();
+    if computed != hash {
+        return Ok(Some(Divergence::TransactionHash {
+            id: *id,
+            stored: hash,
+            computed,
+        }));
+    }
+
+    let recovered = transaction
+        .recover_signer()
+        .map(|recovered| -> [u8; 20] { recovered.into() });
+    if recovered != Some(signer) {
+        return Ok(Some(Divergence::Signature {
+            hash,
+            stored: signer,
+            recovered,
+        }));
+    }
+
+    let (expected_ledger, expected_outputs) =
+        if transaction.to() == Some(Address::from(SYSTEM_ADDRESS)) {
+            match validate_upgrade_by_message(transaction).await {
+                Ok((upgrade_by_message, signer, outputs)) => (
+                    vec![LedgerEntry {
+                        creditor: LEGACY_ACCOUNT,
+                        debtor: signer,
+                        value: outputs.iter().map(|output| output.amount).sum(),
+                    }],
+                    upgrade_by_message.inputs,
+                ),
+                Err(err) => {
+                    return Ok(Some(Divergence::Upgrade {
+                        hash,
+                        reason: err.to_string(),
+                    }))
+                }
+            }
+        } else {
+            (
+                vec![LedgerEntry {
+                    creditor: signer,
+                    debtor: transaction.to().map(Into::into).unwrap_or([0; 20]),
+                    value: scale_down(transaction.value()),
+                }],
+                vec![],
+            )
+        };
+
+    let stored_ledger = get_ledger_by_transaction_id(pool, *id).await?;
+    if stored_ledger != expected_ledger {
+        return Ok(Some(Divergence::Ledger {
+            hash,
+            expected: expected_ledger,
+            stored: stored_ledger,
+        }));
+    }
+
+    let stored_outputs = get_spent_legacy_outputs_by_transaction_id(pool, *id).await?;
+    if stored_outputs != expected_outputs {
+        return Ok(Some(Divergence::SpentLegacyOutputs {
+            hash,
+            expected: expected_outputs,
+            stored: stored_outputs,
+        }));
+    }
+
+    for entry in expected_ledger {
+        *balances.entry(entry.creditor).or_insert(0) -= entry.value;
+        *balances.entry(entry.debtor).or_insert(0) += entry.value;
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::{CHAIN_ID, LAST_LEGACY_BLOCK_TIMESTAMP},
+        evm::{Evm, TransactionSigned},
+        snapshot::{
+            self, Snapshot, SnapshotAccount, SnapshotBlock, SNAPSHOT_FORMAT, SNAPSHOT_VERSION,
+        },
+    };
+    use sqlx::PgPool;
+
+    // 0.1 BTC from 0x1a642f0e3c3af545e7acbd38b07251b3990914f1 to 0x5050a4f4b3f9338c3472dcc01a87c76a144b3c9c
+    const TRANSFER: &str = "f8698080825208945050a4f4b3f9338c3472dcc01a87c76a144b3c9c88016345785d8a000080820188a04a87bef2d40246c0841a6949a8b31e79f3d7884b3a578428cff990f42548e081a036fac8ae104d8f8c21dddc3853c8fc0f1688b48ef5d706991d21809dc73a8850";
+    // Upgrades 136265 sats to 0xf204ee5596cabc6ec60e5e92fd412ea7f856b625
+    const UPGRADE: &str = "f90227068082520894000000000000000000000000000000000000000080b901c4e60b060d0000000000000000000000000000000000000000000000000000000000000040000000000000000000000000000000000000000000000000000000000000014000000000000000000000000000000000000000000000000000000000000000cd416374696f6e3a20557067726164650a44657374696e6174696f6e20436861696e2049443a203230330a44657374696e6174696f6e20416464726573733a203078663230344545353539364341626336456336306535653932466434313245413766383536623632350a496e707574733a0a20202d0a20202020486173683a20343931363865626338323661383263633834633031333936363064396261666239313961366135316432663031626633313632393839363036316533393464300a20202020496e6465783a203000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000004120239ff874c5e9bcdccf84c0e68333d0337f43847d54d56a3b1e339b4b59a975fb59d0741f8857b9467d00ca418d40f3a7f02e4fa4cbd68daf5c29163310724fdf00000000000000000000000000000000000000000000000000000000000000820188a0af7479b422eefc7e6f3051922e63ceb5330fdd08da66c22d5619f2ba514e24faa01ff98a79df5b2b48007e99e810e0d1d6aeed931993401880efef5dae7f46d790";
+
+    fn decode(raw_transaction: &str) -> TransactionSigned {
+        TransactionSigned::decode_rlp_legacy_transaction(
+            &mut &hex::decode(raw_transaction).unwrap()[..],
+        )
+        .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn verify_chain(pool: PgPool) -> sqlx::Result<()> {
+        assert!(super::verify_chain(&pool).await.unwrap().is_none());
+
+        sqlx::query("UPDATE accounts SET balance = balance - 1 WHERE address = $1")
+            .bind(LEGACY_ACCOUNT)
+            .execute(&pool)
+            .await?;
+        assert!(matches!(
+            super::verify_chain(&pool).await.unwrap(),
+            Some(Divergence::Balance {
+                address: LEGACY_ACCOUNT,
+                expected: LEGACY_SUPPLY,
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+    #[sqlx::test]
+    async fn verify_imported_chain(pool: PgPool) -> sqlx::Result<()> {
+        let sender = hex_lit::hex!("1a642f0e3c3af545e7acbd38b07251b3990914f1");
+        let recipient = hex_lit::hex!("5050a4f4b3f9338c3472dcc01a87c76a144b3c9c");
+        snapshot::import(
+            &pool,
+            &Snapshot {
+                format: SNAPSHOT_FORMAT.to_string(),
+                version: SNAPSHOT_VERSION,
+                chain_id: CHAIN_ID,
+                height: LAST_LEGACY_BLOCK_NUMBER + 5,
+                head: Some(SnapshotBlock {
+                    number: LAST_LEGACY_BLOCK_NUMBER + 5,
+                    hash: [1; 32],
+                    timestamp: LAST_LEGACY_BLOCK_TIMESTAMP,
+                    signature: None,
+                }),
+                accounts: vec![
+                    SnapshotAccount {
+                        address: LEGACY_ACCOUNT,
+                        balance: LEGACY_SUPPLY - 100000000,
+                        nonce: 0,
+                    },
+                    SnapshotAccount {
+                        address: sender,
+                        balance: 100000000,
+                        nonce: 0,
+                    },
+                ],
+                contract_state: vec![],
+                spent_legacy_outputs: vec![],
+            },
+        )
+        .await
+        .unwrap();
+        let evm = Evm::new(pool.clone());
+        evm.run_transaction(&decode(TRANSFER)).await.unwrap();
+        evm.run_transaction(&decode(UPGRADE)).await.unwrap();
+        assert!(super::verify_chain(&pool).await.unwrap().is_none());
+
+        sqlx::query("UPDATE accounts SET balance = balance + 1 WHERE address = $1")
+            .bind(recipient)
+            .execute(&pool)
+            .await?;
+        assert!(matches!(
+            super::verify_chain(&pool).await.unwrap(),
+            Some(Divergence::Balance {
+                address,
+                expected: 10000000,
+                ..
+            }) if address == recipient
+        ));
+
+        Ok(())
+    }
+}